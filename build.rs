@@ -0,0 +1,26 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Records the dante-control-rs version the CLI is built against, for `dante-cli info`. It's read
+/// from Cargo.lock since a path dependency's version isn't otherwise visible to the crate.
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR is set by cargo");
+    let lock_path = Path::new(&manifest_dir).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lock_path.display());
+
+    let version = fs::read_to_string(&lock_path)
+        .ok()
+        .and_then(|lock| locked_version(&lock, "dante-control-rs"))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=DANTE_CONTROL_RS_VERSION={}", version);
+}
+
+/// Finds a package's version in Cargo.lock, where it's the line right after the name.
+fn locked_version(lock: &str, package: &str) -> Option<String> {
+    let name_line = format!("name = \"{}\"", package);
+    let lines: Vec<&str> = lock.lines().map(str::trim).collect();
+    let pair = lines.windows(2).find(|pair| pair[0] == name_line)?;
+    let version = pair[1].strip_prefix("version = \"")?.strip_suffix('"')?;
+    Some(version.to_string())
+}
@@ -0,0 +1,98 @@
+use crate::devices::DeviceInfo;
+use crate::output::open_output;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::time::Duration;
+
+/// A device as `--cache` stores it, with the RFC 3339 time it was last seen by a live discovery.
+#[derive(Serialize, Deserialize)]
+struct CachedDevice {
+    #[serde(flatten)]
+    device: DeviceInfo,
+    seen: String,
+}
+
+/// The devices earlier runs discovered, kept in a JSON file so back-to-back control commands can
+/// resolve names and addresses without waiting for discovery each time.
+///
+/// Entries last seen more than the TTL ago are dropped when the file is loaded. A lookup that the
+/// cache can't answer falls back to live discovery, and whatever that finds is written back.
+pub struct DiscoveryCache {
+    path: String,
+    entries: Vec<CachedDevice>,
+}
+
+impl DiscoveryCache {
+    /// Loads the cache at `path`. A missing file is an empty cache, and so is one that can't be
+    /// read, after a warning, since the cache only ever saves time.
+    pub fn load(path: &str, ttl: Duration) -> Self {
+        let entries: Vec<CachedDevice> = match fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|error| {
+                log::warn!("Ignoring discovery cache {}: {}", path, error);
+                Vec::new()
+            }),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => {
+                log::warn!("Ignoring discovery cache {}: {}", path, error);
+                Vec::new()
+            }
+        };
+
+        let now = Utc::now();
+        let entries: Vec<CachedDevice> = entries
+            .into_iter()
+            .filter(|entry| {
+                DateTime::parse_from_rfc3339(&entry.seen)
+                    .ok()
+                    .and_then(|seen| (now - seen.with_timezone(&Utc)).to_std().ok())
+                    .is_some_and(|age| age <= ttl)
+            })
+            .collect();
+        log::info!(
+            "Loaded {} device(s) from discovery cache {}",
+            entries.len(),
+            path
+        );
+        Self {
+            path: path.to_string(),
+            entries,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The devices still within the TTL.
+    pub fn devices(&self) -> Vec<DeviceInfo> {
+        self.entries
+            .iter()
+            .map(|entry| entry.device.clone())
+            .collect()
+    }
+
+    /// Marks `devices` as seen now, replacing any older entry with the same name.
+    pub fn record(&mut self, devices: &[DeviceInfo]) {
+        let seen = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        for device in devices {
+            self.entries
+                .retain(|entry| entry.device.name != device.name);
+            self.entries.push(CachedDevice {
+                device: device.clone(),
+                seen: seen.clone(),
+            });
+        }
+    }
+
+    /// Writes the cache back, replacing the file in one step so a concurrent run never reads half
+    /// of it.
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut out = open_output(Some(&self.path), false)?;
+        serde_json::to_writer_pretty(&mut out, &self.entries)?;
+        writeln!(out)?;
+        out.finish()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,15 @@
+use gag::BufferRedirect;
+use std::io::{self, Read, Write};
+
+/// Runs `print`, which writes straight to stdout, and returns everything it printed instead of
+/// letting it reach the terminal. The output is only available once `print` has returned.
+pub fn capture_stdout(print: impl FnOnce()) -> io::Result<String> {
+    io::stdout().flush()?;
+    let mut redirect = BufferRedirect::stdout()?;
+    print();
+    io::stdout().flush()?;
+
+    let mut captured = String::new();
+    redirect.read_to_string(&mut captured)?;
+    Ok(captured)
+}
@@ -0,0 +1,230 @@
+use clap::{Arg, Command, CommandFactory, FromArgMatches};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use toml::{Table, Value};
+
+const CONFIG_FLAG: &str = "--config";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("Could not read config file {path}: {source}")]
+    Read { path: String, source: io::Error },
+    #[error("Could not parse config file {path}: {source}")]
+    Parse {
+        path: String,
+        source: toml::de::Error,
+    },
+    #[error("Unknown option {key} in config file {path}")]
+    UnknownOption { path: String, key: String },
+    #[error("Unsupported value for {key} in config file {path}, use a string, number, boolean or a list of them")]
+    UnsupportedValue { path: String, key: String },
+}
+
+/// Parses the command line into `T`, using the config file for defaults.
+///
+/// The file is the one given by `--config`, or `dante-cli/config.toml` in the user's config
+/// directory if it exists. Top-level keys set global options and tables set the options of the
+/// subcommand they're named after, with nested tables for nested subcommands, e.g.:
+///
+/// ```toml
+/// verbose = 1
+/// default-version = "4.4.1.3"
+///
+/// [list-devices]
+/// time = 3.0
+///
+/// [control.from-file]
+/// jobs = 4
+/// ```
+///
+/// Values become the options' defaults, so anything given on the command line still wins.
+pub fn parse_with_config<T: CommandFactory + FromArgMatches>() -> Result<T, ConfigError> {
+    let mut command = T::command().arg(
+        Arg::new("config")
+            .long("config")
+            .value_name("PATH")
+            .global(true)
+            .help("Read option defaults from this TOML file instead of ~/.config/dante-cli/config.toml"),
+    );
+
+    let (path, explicit) = match explicit_config_path() {
+        Some(path) => (Some(path), true),
+        None => (default_config_path(), false),
+    };
+    if let Some(path) = path {
+        let display = path.display().to_string();
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let table: Table = contents.parse().map_err(|source| ConfigError::Parse {
+                    path: display.clone(),
+                    source,
+                })?;
+                command = apply_table(command, &table, &display)?;
+            }
+            Err(error) if error.kind() == io::ErrorKind::NotFound && !explicit => {}
+            Err(source) => {
+                return Err(ConfigError::Read {
+                    path: display,
+                    source,
+                })
+            }
+        }
+    }
+
+    let matches = command.get_matches();
+    Ok(T::from_arg_matches(&matches).unwrap_or_else(|error| error.exit()))
+}
+
+/// The `--config` value, found before clap parses anything so the file can feed its defaults.
+fn explicit_config_path() -> Option<PathBuf> {
+    let mut args = env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy().into_owned();
+        if arg == "--" {
+            break;
+        }
+        if arg == CONFIG_FLAG {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+fn default_config_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("dante-cli").join("config.toml"))
+}
+
+fn apply_table(mut command: Command, table: &Table, path: &str) -> Result<Command, ConfigError> {
+    for (key, value) in table {
+        let unknown = || ConfigError::UnknownOption {
+            path: path.to_string(),
+            key: key.clone(),
+        };
+
+        if let Value::Table(subcommand_table) = value {
+            let subcommand = command.find_subcommand(key).cloned().ok_or_else(unknown)?;
+            let subcommand = apply_table(subcommand, subcommand_table, path)?;
+            command = command.mut_subcommand(key, |_| subcommand);
+            continue;
+        }
+
+        let id = key.replace('-', "_");
+        let is_option = command
+            .get_arguments()
+            .any(|arg| arg.get_id() == id.as_str() && !arg.is_positional());
+        if !is_option {
+            return Err(unknown());
+        }
+
+        let values = match value {
+            Value::Array(items) => items.iter().map(scalar).collect::<Option<Vec<_>>>(),
+            value => scalar(value).map(|value| vec![value]),
+        }
+        .ok_or_else(|| ConfigError::UnsupportedValue {
+            path: path.to_string(),
+            key: key.clone(),
+        })?;
+        command = command.mut_arg(id, |arg| arg.default_values(values));
+    }
+    Ok(command)
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(value) => Some(value.clone()),
+        Value::Integer(value) => Some(value.to_string()),
+        Value::Float(value) => Some(value.to_string()),
+        Value::Boolean(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::value_parser;
+
+    const PATH: &str = "config.toml";
+
+    fn command() -> Command {
+        Command::new("dante-cli")
+            .arg(
+                Arg::new("verbose")
+                    .long("verbose")
+                    .value_parser(value_parser!(u8)),
+            )
+            .subcommand(
+                Command::new("list-devices").arg(
+                    Arg::new("time")
+                        .long("time")
+                        .value_parser(value_parser!(u32))
+                        .default_value("3"),
+                ),
+            )
+    }
+
+    fn configured(toml: &str) -> Result<Command, ConfigError> {
+        apply_table(command(), &toml.parse().unwrap(), PATH)
+    }
+
+    fn time(command: Command, args: &[&str]) -> u32 {
+        let matches = command.try_get_matches_from(args).unwrap();
+        let (_, list_devices) = matches.subcommand().unwrap();
+        *list_devices.get_one::<u32>("time").unwrap()
+    }
+
+    #[test]
+    fn unknown_option() {
+        assert!(matches!(
+            configured("colour = 1"),
+            Err(ConfigError::UnknownOption { key, .. }) if key == "colour"
+        ));
+        assert!(matches!(
+            configured("[list-devices]\nspeed = 1"),
+            Err(ConfigError::UnknownOption { key, .. }) if key == "speed"
+        ));
+        assert!(matches!(
+            configured("[monitor]\ntime = 1"),
+            Err(ConfigError::UnknownOption { key, .. }) if key == "monitor"
+        ));
+    }
+
+    #[test]
+    fn wrong_value_type() {
+        assert!(matches!(
+            configured("[list-devices]\ntime = 1979-05-27T07:32:00Z"),
+            Err(ConfigError::UnsupportedValue { key, .. }) if key == "time"
+        ));
+
+        let command = configured("[list-devices]\ntime = \"soon\"").unwrap();
+        assert!(command
+            .try_get_matches_from(["dante-cli", "list-devices"])
+            .is_err());
+    }
+
+    #[test]
+    fn command_line_overrides_config() {
+        let command = configured("verbose = 2\n[list-devices]\ntime = 5").unwrap();
+        assert_eq!(time(command.clone(), &["dante-cli", "list-devices"]), 5);
+        assert_eq!(
+            time(
+                command.clone(),
+                &["dante-cli", "list-devices", "--time", "9"]
+            ),
+            9
+        );
+
+        let matches = command
+            .try_get_matches_from(["dante-cli", "--verbose", "1", "list-devices"])
+            .unwrap();
+        assert_eq!(matches.get_one::<u8>("verbose"), Some(&1));
+    }
+}
@@ -0,0 +1,32 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+/// Clap value parser for `--deadline`.
+pub fn parse_deadline(value: &str) -> Result<DateTime<FixedOffset>, String> {
+    let deadline = DateTime::parse_from_rfc3339(value).map_err(|error| error.to_string())?;
+    if deadline.with_timezone(&Utc) <= Utc::now() {
+        return Err(format!("{} is already in the past", deadline.to_rfc3339()));
+    }
+    Ok(deadline)
+}
+
+/// Aborts the process if it's still running at `deadline`, reporting how many control operations
+/// had finished by then. This runs on its own thread so it also cuts through a blocked discovery
+/// wait or a hung device.
+pub fn spawn_watchdog(deadline: DateTime<FixedOffset>, completed: Arc<AtomicUsize>) {
+    thread::spawn(move || {
+        if let Ok(remaining) = (deadline.with_timezone(&Utc) - Utc::now()).to_std() {
+            thread::sleep(remaining);
+        }
+
+        eprintln!(
+            "Deadline {} reached before the command finished, {} control operation(s) had completed",
+            deadline.to_rfc3339(),
+            completed.load(Ordering::SeqCst)
+        );
+        process::exit(1);
+    });
+}
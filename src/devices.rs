@@ -0,0 +1,1017 @@
+use crate::cache::DiscoveryCache;
+use crate::retry::RetryPolicy;
+use dante_control_rs::DanteDeviceManager;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, IsTerminal, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+/// A discovered device, assembled from the name and description strings exposed by
+/// `DanteDeviceManager`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub ipv4: Option<Ipv4Addr>,
+    pub description: String,
+}
+
+/// A `--filter` pattern matched against device names.
+#[derive(Debug, Clone)]
+pub enum NameFilter {
+    Substring {
+        needle: String,
+        case_sensitive: bool,
+    },
+    Regex(Regex),
+}
+
+impl NameFilter {
+    pub fn new(pattern: &str, regex: bool, case_sensitive: bool) -> Result<Self, regex::Error> {
+        if regex {
+            return RegexBuilder::new(pattern)
+                .case_insensitive(!case_sensitive)
+                .build()
+                .map(NameFilter::Regex);
+        }
+
+        Ok(NameFilter::Substring {
+            needle: if case_sensitive {
+                pattern.to_string()
+            } else {
+                pattern.to_lowercase()
+            },
+            case_sensitive,
+        })
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            NameFilter::Substring {
+                needle,
+                case_sensitive: true,
+            } => name.contains(needle.as_str()),
+            NameFilter::Substring { needle, .. } => name.to_lowercase().contains(needle.as_str()),
+            NameFilter::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SnapshotError {
+    #[error("Could not open snapshot file {path}: {source}")]
+    Open { path: String, source: io::Error },
+    #[error("Could not parse snapshot file {path}: {source}")]
+    Parse {
+        path: String,
+        source: serde_json::Error,
+    },
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ResolveError {
+    #[error("No discovered device has the IP address {0}")]
+    NoDeviceWithIp(Ipv4Addr),
+    #[error("No discovered device is named {0}")]
+    NoDeviceNamed(String),
+    #[error("Device {0} was discovered but has no IPv4 address")]
+    NoAddress(String),
+    #[error("{count} discovered devices are named {name}")]
+    Ambiguous { name: String, count: usize },
+    #[error("{name} is part of the names of {} devices: {}", matches.len(), matches.join(", "))]
+    AmbiguousPartial { name: String, matches: Vec<String> },
+    #[error("Device {name} only reported the IPv6 address {address}, and Dante control only works over IPv4")]
+    OnlyIpv6 { name: String, address: Ipv6Addr },
+    #[error("Transmitter {0} not found on network")]
+    TransmitterNotFound(String),
+    #[error("{0} of the names or addresses looked up matched no discovered device")]
+    NotFound(usize),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum AddressError {
+    #[error("{0} is an IPv6 address, but Dante control only works over IPv4")]
+    Ipv6(Ipv6Addr),
+    #[error("{0:?} is not a valid IPv4 address")]
+    Invalid(String),
+    #[error("{0} line(s) aren't valid IPv4 addresses")]
+    InvalidLines(usize),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DiscoveryError {
+    #[error("Failed to start mDNS discovery. Is another Dante controller running, or is multicast blocked on this interface? Run with --verbose for the underlying error.")]
+    Start,
+    #[error("Not allowed to open the mDNS socket. Run as root, or grant the binary raw socket access with `sudo setcap cap_net_raw+ep $(which dante-cli)`. Run with --verbose for the underlying error.")]
+    PermissionDenied,
+    #[error("The mDNS port 5353 is already in use by a program that doesn't share it, most likely another Dante controller or mDNS responder. Stop it and try again, or run with --verbose for the underlying error.")]
+    AddrInUse,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProbeError {
+    #[error("Could not reach {0}")]
+    Unreachable(String),
+}
+
+/// How a device differs from the snapshot, or the previous monitor interval, it's compared against.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    New,
+    Changed,
+    Removed,
+}
+
+/// A device field that change detection compares, for `--change-fields`. Devices are matched up
+/// by name, so a name is never a change of its own.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeField {
+    /// The IPv4 address
+    Ip,
+    /// The whole mDNS description, including anything volatile a device puts in it
+    Description,
+}
+
+/// A single attribute that differs between the snapshot and the live device.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldDelta {
+    pub field: &'static str,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DeviceChange {
+    pub name: String,
+    pub change: ChangeKind,
+    pub deltas: Vec<FieldDelta>,
+}
+
+/// Sets how many times `start_discovery` retries, for `--discovery-retries`. Discovery is started
+/// from many places, so this is set once up front rather than passed down to each of them.
+pub fn set_discovery_retries(retries: u32) {
+    let _ = DISCOVERY_RETRY.set(RetryPolicy::with_backoff(retries, DISCOVERY_RETRY_DELAY));
+}
+
+/// Starts discovery, swapping the library's error for one that says what to check. The original
+/// error is logged as a warning so it still shows up with `--verbose`. Failed starts are retried
+/// as `--discovery-retries` asks.
+pub fn start_discovery(device_manager: &DanteDeviceManager) -> Result<(), DiscoveryError> {
+    let retry = DISCOVERY_RETRY
+        .get()
+        .copied()
+        .unwrap_or_else(|| RetryPolicy::new(0, DISCOVERY_RETRY_DELAY));
+    retry
+        .run(|| device_manager.start_discovery())
+        .map_err(|error| {
+            log::warn!("mDNS discovery failed to start: {}", error);
+            match io_error_kind(&error) {
+                Some(io::ErrorKind::PermissionDenied) => DiscoveryError::PermissionDenied,
+                Some(io::ErrorKind::AddrInUse) => DiscoveryError::AddrInUse,
+                _ => DiscoveryError::Start,
+            }
+        })
+}
+
+/// Finds the kind of the first I/O error in an error's source chain.
+fn io_error_kind(error: &(dyn std::error::Error + 'static)) -> Option<io::ErrorKind> {
+    let mut next = Some(error);
+    while let Some(error) = next {
+        if let Some(io_error) = error.downcast_ref::<io::Error>() {
+            return Some(io_error.kind());
+        }
+        next = error.source();
+    }
+    None
+}
+
+/// Collects the devices the device manager currently knows about.
+///
+/// The manager only hands out names and preformatted descriptions, so each name is paired with a
+/// description that contains it (longest names first, so "Stage" can't claim the description of
+/// "Stage-2"), and the IPv4 address is read out of that description.
+pub fn collect_devices(device_manager: &DanteDeviceManager) -> Vec<DeviceInfo> {
+    let names: Vec<String> = device_manager
+        .get_device_names()
+        .into_iter()
+        .map(|name| name.to_string())
+        .collect();
+    let mut descriptions: Vec<Option<String>> = device_manager
+        .get_device_descriptions()
+        .into_iter()
+        .map(|description| Some(description.to_string()))
+        .collect();
+
+    let mut by_length: Vec<usize> = (0..names.len()).collect();
+    by_length.sort_by_key(|&index| std::cmp::Reverse(names[index].len()));
+
+    let mut matched = vec![String::new(); names.len()];
+    for index in by_length {
+        let name = &names[index];
+        if let Some(description) = descriptions
+            .iter_mut()
+            .find(|description| description.as_ref().is_some_and(|d| d.contains(name)))
+        {
+            matched[index] = description.take().unwrap_or_default();
+        }
+    }
+
+    names
+        .into_iter()
+        .zip(matched)
+        .map(|(name, description)| DeviceInfo {
+            ipv4: find_ipv4(&description),
+            name,
+            description,
+        })
+        .collect()
+}
+
+/// How often discovery results are re-checked while waiting for a device to show up.
+const RESOLVE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The wait before retrying a failed discovery start, doubling with each retry after that.
+const DISCOVERY_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+static DISCOVERY_RETRY: OnceLock<RetryPolicy> = OnceLock::new();
+
+/// How often the device count is sampled, and the progress line redrawn, during a discovery wait.
+pub const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Frames of the spinner drawn on stderr during a discovery wait.
+const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Whether discovery waits should draw a progress line: not under `--quiet`, and only when stderr
+/// is a terminal that can redraw it in place.
+pub fn show_progress(quiet: bool) -> bool {
+    !quiet && io::stderr().is_terminal()
+}
+
+/// The one-line discovery progress display on stderr: a spinner, the time left and, where it can
+/// be counted, how many devices have turned up so far. A disabled display draws nothing.
+pub struct Progress {
+    enabled: bool,
+    drawn: bool,
+    spinner: std::iter::Cycle<std::slice::Iter<'static, char>>,
+}
+
+impl Progress {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            drawn: false,
+            spinner: SPINNER.iter().cycle(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Redraws the line with the next spinner frame.
+    pub fn update(&mut self, left: Duration, devices: Option<usize>) {
+        if !self.enabled {
+            return;
+        }
+        let frame = self.spinner.next().unwrap_or(&' ');
+        match devices {
+            Some(count) => eprint!(
+                "\r{} {:.1}s left, {} device(s) found so far",
+                frame,
+                left.as_secs_f32(),
+                count
+            ),
+            None => eprint!("\r{} {:.1}s left", frame, left.as_secs_f32()),
+        }
+        let _ = io::stderr().flush();
+        self.drawn = true;
+    }
+
+    /// Clears the line again, if anything was drawn.
+    pub fn clear(&mut self) {
+        if self.drawn {
+            eprint!("\r\x1b[2K");
+            let _ = io::stderr().flush();
+            self.drawn = false;
+        }
+    }
+}
+
+/// Runs `work`, which waits about `time` for discovery somewhere it can't be sampled, while a
+/// progress line counts that time down from a scoped thread. The line is cleared before this
+/// returns, so nothing `work` left to print afterwards is mixed into it.
+pub fn with_countdown<T>(enabled: bool, time: Duration, work: impl FnOnce() -> T) -> T {
+    if !enabled {
+        return work();
+    }
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let countdown = scope.spawn(|| {
+            let deadline = Instant::now() + time;
+            let mut progress = Progress::new(true);
+            while !done.load(Ordering::Relaxed) {
+                progress.update(deadline.saturating_duration_since(Instant::now()), None);
+                sleep(WAIT_POLL_INTERVAL);
+            }
+            progress.clear();
+        });
+        let result = work();
+        done.store(true, Ordering::Relaxed);
+        let _ = countdown.join();
+        result
+    })
+}
+
+/// Records when devices turned up during a discovery wait, for `--timing`.
+#[derive(Debug)]
+pub struct DiscoveryTiming {
+    started: Instant,
+    finished: Option<Duration>,
+    first_device: Option<Duration>,
+    last_new_device: Option<Duration>,
+    device_count: usize,
+}
+
+impl DiscoveryTiming {
+    /// Starts the clock. Call this right before starting discovery.
+    pub fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            finished: None,
+            first_device: None,
+            last_new_device: None,
+            device_count: 0,
+        }
+    }
+
+    fn observe(&mut self, device_count: usize) {
+        if device_count > self.device_count {
+            let elapsed = self.started.elapsed();
+            self.first_device.get_or_insert(elapsed);
+            self.last_new_device = Some(elapsed);
+            self.device_count = device_count;
+        }
+    }
+
+    fn finish(&mut self) {
+        self.finished = Some(self.started.elapsed());
+    }
+
+    /// Prints the timings to stderr so they never mix with data on stdout.
+    pub fn report(&self) {
+        let total = self.finished.unwrap_or_else(|| self.started.elapsed());
+        eprintln!(
+            "Discovery took {:.2}s and found {} device(s)",
+            total.as_secs_f32(),
+            self.device_count
+        );
+        if let (Some(first), Some(last)) = (self.first_device, self.last_new_device) {
+            eprintln!(
+                "First device after {:.2}s, last new device after {:.2}s",
+                first.as_secs_f32(),
+                last.as_secs_f32()
+            );
+        }
+    }
+}
+
+/// Waits out a fixed discovery window, sampling how many devices have been found as it goes. With
+/// `show_progress` a [`Progress`] line is kept on stderr, and cleared again once the wait is over.
+/// The wait ends early once a device named `until_found` turns up, or once `max_devices` devices
+/// are known.
+pub fn wait_for_devices(
+    device_manager: &DanteDeviceManager,
+    time: Duration,
+    timing: &mut DiscoveryTiming,
+    show_progress: bool,
+    until_found: Option<&str>,
+    max_devices: Option<usize>,
+) {
+    let deadline = Instant::now() + time;
+    let mut progress = Progress::new(show_progress);
+    loop {
+        let names = device_manager.get_device_names();
+        let count = names.len();
+        timing.observe(count);
+        let found =
+            until_found.is_some_and(|wanted| names.iter().any(|name| name.to_string() == wanted));
+        let full = max_devices.is_some_and(|max| count >= max);
+        let now = Instant::now();
+        if found || full || now >= deadline {
+            break;
+        }
+        progress.update(deadline - now, Some(count));
+        sleep(WAIT_POLL_INTERVAL.min(deadline - now));
+    }
+    progress.clear();
+    timing.finish();
+}
+
+/// Whether discovery found at least `min_devices` devices, including every name in `wait_for`.
+pub fn has_expected_devices(
+    devices: &[DeviceInfo],
+    min_devices: usize,
+    wait_for: &[String],
+) -> bool {
+    devices.len() >= min_devices
+        && wait_for
+            .iter()
+            .all(|name| devices.iter().any(|device| &device.name == name))
+}
+
+/// How to order device listings.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    Name,
+    /// By IPv4 address, with devices that have none last
+    Ip,
+    /// The order devices were discovered in
+    None,
+}
+
+/// Sorts devices so output is stable from run to run. Ties are broken by name.
+pub fn sort_devices(devices: &mut [DeviceInfo], order: SortOrder) {
+    match order {
+        SortOrder::Name => devices.sort_by(|a, b| a.name.cmp(&b.name)),
+        SortOrder::Ip => devices.sort_by(|a, b| {
+            (a.ipv4.is_none(), a.ipv4, &a.name).cmp(&(b.ipv4.is_none(), b.ipv4, &b.name))
+        }),
+        SortOrder::None => {}
+    }
+}
+
+/// How to bucket a `--group-by` listing.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupBy {
+    /// The /24 network of each device's IPv4 address
+    Subnet,
+    /// The part of each name before the first -
+    NamePrefix,
+}
+
+/// Splits devices into groups, in the order each group first appears, keeping the order of the
+/// devices within each group.
+pub fn group_devices(devices: &[DeviceInfo], by: GroupBy) -> Vec<(String, Vec<DeviceInfo>)> {
+    let mut groups: Vec<(String, Vec<DeviceInfo>)> = Vec::new();
+    for device in devices {
+        let key = match by {
+            GroupBy::Subnet => match device.ipv4 {
+                Some(ip) => {
+                    let [a, b, c, _] = ip.octets();
+                    format!("{}.{}.{}.0/24", a, b, c)
+                }
+                None => "no IPv4 address".to_string(),
+            },
+            GroupBy::NamePrefix => device
+                .name
+                .split_once('-')
+                .map_or(device.name.as_str(), |(prefix, _)| prefix)
+                .to_string(),
+        };
+        match groups.iter_mut().find(|(known, _)| *known == key) {
+            Some((_, group)) => group.push(device.clone()),
+            None => groups.push((key, vec![device.clone()])),
+        }
+    }
+    groups
+}
+
+/// Adds the devices in `found` to `devices`, replacing the details of any already there with the
+/// newer ones. Returns how many were new.
+pub fn merge_devices(devices: &mut Vec<DeviceInfo>, found: Vec<DeviceInfo>) -> usize {
+    let mut added = 0;
+    for device in found {
+        match devices.iter_mut().find(|known| known.name == device.name) {
+            Some(known) => *known = device,
+            None => {
+                devices.push(device);
+                added += 1;
+            }
+        }
+    }
+    added
+}
+
+/// Keeps only the devices whose name matches `filter`.
+pub fn retain_matching(devices: &mut Vec<DeviceInfo>, filter: &NameFilter) {
+    devices.retain(|device| filter.matches(&device.name));
+}
+
+/// Keeps only the first `max` devices in discovery order, for `--max-devices`. Returns whether any
+/// were dropped.
+pub fn truncate_devices(devices: &mut Vec<DeviceInfo>, max: Option<usize>) -> bool {
+    match max {
+        Some(max) if devices.len() > max => {
+            devices.truncate(max);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Drops the devices whose name matches any of the `--exclude` filters.
+pub fn remove_matching(devices: &mut Vec<DeviceInfo>, excludes: &[NameFilter]) {
+    devices.retain(|device| !excludes.iter().any(|exclude| exclude.matches(&device.name)));
+}
+
+/// Runs discovery until `find` succeeds against the devices seen so far, giving up with the last
+/// error from `find` once `time` has passed. A freshly started discovery often hasn't heard from
+/// every device yet, so this waits only as long as it has to rather than failing outright.
+pub fn discover_until<T, E>(
+    time: Duration,
+    timing: &mut DiscoveryTiming,
+    mut find: impl FnMut(&[DeviceInfo]) -> Result<T, E>,
+) -> Result<T, Box<dyn std::error::Error>>
+where
+    E: std::error::Error + 'static,
+{
+    let device_manager = DanteDeviceManager::new();
+    start_discovery(&device_manager)?;
+
+    let result = poll_until(&device_manager, Instant::now() + time, timing, find);
+    device_manager.stop_discovery();
+    timing.finish();
+    Ok(result?)
+}
+
+/// Polls a running discovery until `find` succeeds or `deadline` passes.
+fn poll_until<T, E>(
+    device_manager: &DanteDeviceManager,
+    deadline: Instant,
+    timing: &mut DiscoveryTiming,
+    mut find: impl FnMut(&[DeviceInfo]) -> Result<T, E>,
+) -> Result<T, E>
+where
+    E: std::fmt::Display,
+{
+    loop {
+        let devices = collect_devices(device_manager);
+        timing.observe(devices.len());
+        match find(&devices) {
+            Ok(found) => return Ok(found),
+            Err(error) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(error);
+                }
+                log::info!(
+                    "{}, waiting up to {:.1}s more for discovery",
+                    error,
+                    (deadline - now).as_secs_f32()
+                );
+                sleep(RESOLVE_POLL_INTERVAL.min(deadline - now));
+            }
+        }
+    }
+}
+
+/// One discovery shared by every lookup a control command makes, so resolving a transmitter and
+/// several receivers waits for discovery once rather than once per name. Discovery starts with the
+/// first lookup, every lookup gives up `time` after that, and the same device manager then sends
+/// the subscriptions. With a `--cache`, a lookup the cached devices can answer doesn't start
+/// discovery at all.
+pub struct SharedDiscovery {
+    device_manager: DanteDeviceManager,
+    time: Duration,
+    deadline: Option<Instant>,
+    timing: DiscoveryTiming,
+    cache: Option<DiscoveryCache>,
+}
+
+impl SharedDiscovery {
+    pub fn new(time: Duration, cache: Option<DiscoveryCache>) -> Self {
+        Self {
+            device_manager: DanteDeviceManager::new(),
+            time,
+            deadline: None,
+            timing: DiscoveryTiming::start(),
+            cache,
+        }
+    }
+
+    /// Like [`discover_until`], against the shared discovery.
+    pub fn find<T, E>(
+        &mut self,
+        mut find: impl FnMut(&[DeviceInfo]) -> Result<T, E>,
+    ) -> Result<T, Box<dyn std::error::Error>>
+    where
+        E: std::error::Error + 'static,
+    {
+        if let Some(cache) = &self.cache {
+            match find(&cache.devices()) {
+                Ok(found) => {
+                    log::info!("Answered from discovery cache {}", cache.path());
+                    return Ok(found);
+                }
+                Err(error) => log::info!("{} in discovery cache {}", error, cache.path()),
+            }
+        }
+        self.find_live(find)
+    }
+
+    /// Like `find`, but always against live discovery, for checks the cache can't stand in for.
+    pub fn find_live<T, E>(
+        &mut self,
+        find: impl FnMut(&[DeviceInfo]) -> Result<T, E>,
+    ) -> Result<T, Box<dyn std::error::Error>>
+    where
+        E: std::error::Error + 'static,
+    {
+        let deadline = match self.deadline {
+            Some(deadline) => deadline,
+            None => {
+                self.timing = DiscoveryTiming::start();
+                start_discovery(&self.device_manager)?;
+                *self.deadline.insert(Instant::now() + self.time)
+            }
+        };
+
+        let result = poll_until(&self.device_manager, deadline, &mut self.timing, find);
+        self.timing.finish();
+        if let Some(cache) = &mut self.cache {
+            cache.record(&collect_devices(&self.device_manager));
+            if let Err(error) = cache.save() {
+                log::warn!(
+                    "Could not write discovery cache {}: {}",
+                    cache.path(),
+                    error
+                );
+            }
+        }
+        Ok(result?)
+    }
+
+    /// Prints the `--timing` report, if any lookup needed discovery.
+    pub fn report_timing(&self) {
+        if self.deadline.is_some() {
+            self.timing.report();
+        }
+    }
+
+    pub fn device_manager(&mut self) -> &mut DanteDeviceManager {
+        &mut self.device_manager
+    }
+}
+
+impl Drop for SharedDiscovery {
+    fn drop(&mut self) {
+        if self.deadline.is_some() {
+            self.device_manager.stop_discovery();
+        }
+    }
+}
+
+/// Waits for every receiver to show up in the shared discovery and returns their names. The cache
+/// is passed over, since only a live answer shows a receiver is reachable.
+pub fn probe_receivers(
+    receivers: &[Ipv4Addr],
+    discovery: &mut SharedDiscovery,
+) -> Result<Vec<(Ipv4Addr, String)>, Box<dyn std::error::Error>> {
+    discovery.find_live(|devices| {
+        let mut found = Vec::new();
+        let mut missing = Vec::new();
+        for ip in receivers {
+            match name_for_ip(devices, *ip) {
+                Ok(name) => found.push((*ip, name)),
+                Err(_) => missing.push(ip.to_string()),
+            }
+        }
+
+        if missing.is_empty() {
+            Ok(found)
+        } else {
+            Err(ProbeError::Unreachable(missing.join(", ")))
+        }
+    })
+}
+
+/// Finds the name of the device with the given address.
+pub fn name_for_ip(devices: &[DeviceInfo], ip: Ipv4Addr) -> Result<String, ResolveError> {
+    devices
+        .iter()
+        .find(|device| device.ipv4 == Some(ip))
+        .map(|device| device.name.clone())
+        .ok_or(ResolveError::NoDeviceWithIp(ip))
+}
+
+/// Finds the names of every device with the given address, for `resolve --ip`. More than one
+/// device can answer from the same address, such as a card and the host it's in.
+pub fn names_for_ip(devices: &[DeviceInfo], ip: Ipv4Addr) -> Result<Vec<String>, ResolveError> {
+    let names: Vec<String> = devices
+        .iter()
+        .filter(|device| device.ipv4 == Some(ip))
+        .map(|device| device.name.clone())
+        .collect();
+    if names.is_empty() {
+        return Err(ResolveError::NoDeviceWithIp(ip));
+    }
+    Ok(names)
+}
+
+/// Checks that a device is discovered under each transmitter name, for `--verify-transmitter`.
+/// The device manager doesn't list a device's channels, so only the device itself is checked.
+pub fn find_transmitters(devices: &[DeviceInfo], names: &[String]) -> Result<(), ResolveError> {
+    match names
+        .iter()
+        .find(|name| !devices.iter().any(|device| &device.name == *name))
+    {
+        Some(missing) => Err(ResolveError::TransmitterNotFound(missing.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Finds the one device with the given name. With `fuzzy`, a name that no device has exactly can
+/// still pick out a single device whose name contains it, ignoring case.
+pub fn device_named<'a>(
+    devices: &'a [DeviceInfo],
+    name: &str,
+    fuzzy: bool,
+) -> Result<&'a DeviceInfo, ResolveError> {
+    let matches: Vec<&DeviceInfo> = devices
+        .iter()
+        .filter(|device| device.name == name)
+        .collect();
+    match matches.as_slice() {
+        [device] => return Ok(device),
+        [] => {}
+        _ => {
+            return Err(ResolveError::Ambiguous {
+                name: name.to_string(),
+                count: matches.len(),
+            })
+        }
+    }
+    if !fuzzy {
+        return Err(ResolveError::NoDeviceNamed(name.to_string()));
+    }
+
+    let needle = name.to_lowercase();
+    let matches: Vec<&DeviceInfo> = devices
+        .iter()
+        .filter(|device| device.name.to_lowercase().contains(&needle))
+        .collect();
+    match matches.as_slice() {
+        [] => Err(ResolveError::NoDeviceNamed(name.to_string())),
+        [device] => {
+            log::info!("{} matched device {}", name, device.name);
+            Ok(device)
+        }
+        _ => Err(ResolveError::AmbiguousPartial {
+            name: name.to_string(),
+            matches: matches.iter().map(|device| device.name.clone()).collect(),
+        }),
+    }
+}
+
+/// Finds the address of the device with the given name, refusing to pick one if several devices
+/// share it. `fuzzy` is passed on to [`device_named`].
+pub fn ip_for_name(
+    devices: &[DeviceInfo],
+    name: &str,
+    fuzzy: bool,
+) -> Result<Ipv4Addr, ResolveError> {
+    let device = device_named(devices, name, fuzzy)?;
+    device
+        .ipv4
+        .ok_or_else(|| match find_ipv6(&device.description) {
+            Some(address) => ResolveError::OnlyIpv6 {
+                name: device.name.clone(),
+                address,
+            },
+            None => ResolveError::NoAddress(device.name.clone()),
+        })
+}
+
+/// Parses an IPv4 address, saying so plainly when it's given an IPv6 one instead.
+pub fn parse_ipv4(value: &str) -> Result<Ipv4Addr, AddressError> {
+    Ipv4Addr::from_str(value).map_err(|_| match parse_ipv6(value) {
+        Some(address) => AddressError::Ipv6(address),
+        None => AddressError::Invalid(value.to_string()),
+    })
+}
+
+/// Rejects IPv6 addresses where either an IPv4 address or a device name is expected, so they
+/// aren't mistaken for a name.
+pub fn reject_ipv6(value: &str) -> Result<(), AddressError> {
+    match parse_ipv6(value) {
+        Some(address) => Err(AddressError::Ipv6(address)),
+        None => Ok(()),
+    }
+}
+
+/// Parses an IPv6 address, ignoring any `%zone` suffix on link-local addresses.
+fn parse_ipv6(value: &str) -> Option<Ipv6Addr> {
+    let address = value.split_once('%').map_or(value, |(address, _)| address);
+    Ipv6Addr::from_str(address).ok()
+}
+
+/// Finds an IPv6 address in a description, for explaining why a device has no usable address.
+fn find_ipv6(description: &str) -> Option<Ipv6Addr> {
+    description
+        .split(|c: char| c.is_whitespace() || matches!(c, ',' | '(' | ')' | '[' | ']'))
+        .filter(|token| token.contains(':'))
+        .find_map(parse_ipv6)
+}
+
+/// Finds the device address in a description. Descriptions can also contain dotted version
+/// strings like "4.4.1.3", so private and link-local addresses (where Dante devices live) are
+/// preferred over anything else that happens to parse.
+fn find_ipv4(description: &str) -> Option<Ipv4Addr> {
+    let candidates: Vec<Ipv4Addr> = description
+        .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .filter_map(|token| token.parse().ok())
+        .collect();
+
+    candidates
+        .iter()
+        .find(|ip| ip.is_private() || ip.is_link_local())
+        .or(candidates.first())
+        .copied()
+}
+
+/// Loads a snapshot previously written by `list-devices --format json --detailed`.
+pub fn load_snapshot(path: &str) -> Result<Vec<DeviceInfo>, SnapshotError> {
+    let file = File::open(path).map_err(|source| SnapshotError::Open {
+        path: path.to_string(),
+        source,
+    })?;
+
+    serde_json::from_reader(io::BufReader::new(file)).map_err(|source| SnapshotError::Parse {
+        path: path.to_string(),
+        source,
+    })
+}
+
+/// Lists the devices in `current` that are new or whose attributes differ from `baseline`.
+/// Devices are matched up by name.
+pub fn diff_devices(
+    baseline: &[DeviceInfo],
+    current: &[DeviceInfo],
+    fields: &[ChangeField],
+) -> Vec<DeviceChange> {
+    current
+        .iter()
+        .filter_map(
+            |device| match baseline.iter().find(|old| old.name == device.name) {
+                None => Some(DeviceChange {
+                    name: device.name.clone(),
+                    change: ChangeKind::New,
+                    deltas: field_deltas(None, device, fields),
+                }),
+                Some(old) => {
+                    let deltas = field_deltas(Some(old), device, fields);
+                    (!deltas.is_empty()).then(|| DeviceChange {
+                        name: device.name.clone(),
+                        change: ChangeKind::Changed,
+                        deltas,
+                    })
+                }
+            },
+        )
+        .collect()
+}
+
+/// Like `diff_devices`, but also reports devices from `previous` that are gone from `current`.
+pub fn diff_intervals(
+    previous: &[DeviceInfo],
+    current: &[DeviceInfo],
+    fields: &[ChangeField],
+) -> Vec<DeviceChange> {
+    let mut changes = diff_devices(previous, current, fields);
+    changes.extend(
+        previous
+            .iter()
+            .filter(|old| !current.iter().any(|device| device.name == old.name))
+            .map(|old| DeviceChange {
+                name: old.name.clone(),
+                change: ChangeKind::Removed,
+                deltas: Vec::new(),
+            }),
+    );
+    changes
+}
+
+fn field_deltas(
+    before: Option<&DeviceInfo>,
+    after: &DeviceInfo,
+    compared: &[ChangeField],
+) -> Vec<FieldDelta> {
+    let fields: [(ChangeField, &'static str, fn(&DeviceInfo) -> Option<String>); 2] = [
+        (ChangeField::Ip, "ipv4", |device| {
+            device.ipv4.map(|ip| ip.to_string())
+        }),
+        (ChangeField::Description, "description", |device| {
+            Some(device.description.clone())
+        }),
+    ];
+
+    fields
+        .into_iter()
+        .filter(|(kind, _, _)| compared.contains(kind))
+        .filter_map(|(_, field, value)| {
+            let before = before.and_then(value);
+            let after = value(after);
+            (before != after).then_some(FieldDelta {
+                field,
+                before,
+                after,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_FIELDS: &[ChangeField] = &[ChangeField::Ip, ChangeField::Description];
+
+    fn device(name: &str, ip: [u8; 4], description: &str) -> DeviceInfo {
+        DeviceInfo {
+            name: name.to_string(),
+            ipv4: Some(Ipv4Addr::from(ip)),
+            description: description.to_string(),
+        }
+    }
+
+    fn kinds(changes: &[DeviceChange]) -> Vec<(&str, ChangeKind)> {
+        changes
+            .iter()
+            .map(|change| (change.name.as_str(), change.change))
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_devices() {
+        let devices = [device("Stage", [10, 0, 0, 5], "Stage")];
+        assert!(diff_devices(&devices, &devices, ALL_FIELDS).is_empty());
+        assert!(diff_intervals(&devices, &devices, ALL_FIELDS).is_empty());
+    }
+
+    #[test]
+    fn new_device() {
+        let baseline = [device("Stage", [10, 0, 0, 5], "Stage")];
+        let current = [
+            device("Stage", [10, 0, 0, 5], "Stage"),
+            device("Booth", [10, 0, 0, 6], "Booth"),
+        ];
+        let changes = diff_devices(&baseline, &current, ALL_FIELDS);
+        assert_eq!(kinds(&changes), vec![("Booth", ChangeKind::New)]);
+        assert_eq!(
+            changes[0].deltas[0],
+            FieldDelta {
+                field: "ipv4",
+                before: None,
+                after: Some("10.0.0.6".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn changed_device() {
+        let baseline = [device("Stage", [10, 0, 0, 5], "Stage")];
+        let current = [device("Stage", [10, 0, 0, 9], "Stage")];
+        let changes = diff_devices(&baseline, &current, ALL_FIELDS);
+        assert_eq!(kinds(&changes), vec![("Stage", ChangeKind::Changed)]);
+        assert_eq!(
+            changes[0].deltas,
+            vec![FieldDelta {
+                field: "ipv4",
+                before: Some("10.0.0.5".to_string()),
+                after: Some("10.0.0.9".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn removed_device() {
+        let previous = [
+            device("Stage", [10, 0, 0, 5], "Stage"),
+            device("Booth", [10, 0, 0, 6], "Booth"),
+        ];
+        let current = [device("Stage", [10, 0, 0, 5], "Stage")];
+        assert!(diff_devices(&previous, &current, ALL_FIELDS).is_empty());
+        assert_eq!(
+            kinds(&diff_intervals(&previous, &current, ALL_FIELDS)),
+            vec![("Booth", ChangeKind::Removed)]
+        );
+    }
+
+    #[test]
+    fn change_fields_limit_what_counts() {
+        let baseline = [device("Stage", [10, 0, 0, 5], "Stage uptime=1")];
+        let current = [device("Stage", [10, 0, 0, 5], "Stage uptime=2")];
+        assert!(diff_devices(&baseline, &current, &[ChangeField::Ip]).is_empty());
+
+        let changes = diff_devices(&baseline, &current, &[ChangeField::Description]);
+        assert_eq!(kinds(&changes), vec![("Stage", ChangeKind::Changed)]);
+        assert_eq!(changes[0].deltas.len(), 1);
+        assert_eq!(changes[0].deltas[0].field, "description");
+    }
+}
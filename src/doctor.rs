@@ -0,0 +1,334 @@
+use crate::capture::capture_stdout;
+use crate::devices::start_discovery;
+use dante_control_rs::DanteDeviceManager;
+use serde::Serialize;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, UdpSocket};
+use std::thread::sleep;
+use std::time::Duration;
+
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<&'static str>,
+}
+
+/// Output format for `doctor`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DoctorFormat {
+    Text,
+    /// One object with every check and an overall pass or fail
+    Json,
+}
+
+/// The `doctor --format json` report.
+#[derive(Serialize)]
+struct Report<'a> {
+    checks: &'a [Check],
+    overall: &'static str,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DoctorError {
+    #[error("{0} diagnostic check(s) failed")]
+    ChecksFailed(usize),
+}
+
+impl Check {
+    fn new(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn hint(mut self, hint: &'static str) -> Self {
+        self.hint = Some(hint);
+        self
+    }
+}
+
+/// An mDNS service along with the debug function that browses for it and prints what answers.
+pub type ServiceBrowser = (&'static str, fn(Duration));
+
+/// Runs every environment check, spending `discovery_time` on a trial discovery and
+/// `service_time` browsing each of `services`.
+pub fn run_checks(
+    discovery_time: Duration,
+    services: &[ServiceBrowser],
+    service_time: Duration,
+) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let addresses = match local_ipv4_addresses() {
+        Ok(addresses) => addresses,
+        Err(error) => {
+            checks.push(Check::new(
+                "Network interfaces",
+                CheckStatus::Fail,
+                format!("Could not enumerate network interfaces: {}", error),
+            ));
+            Vec::new()
+        }
+    };
+
+    checks.push(check_dante_interface(&addresses));
+    checks.push(check_mdns_port());
+
+    let multicast = check_multicast(&addresses);
+    let multicast_works = multicast.status == CheckStatus::Pass;
+    checks.push(multicast);
+
+    let discovered = match discover_device_count(discovery_time) {
+        Ok(count) => {
+            checks.push(if count > 0 {
+                Check::new(
+                    "Discovery",
+                    CheckStatus::Pass,
+                    format!("Found {} device(s)", count),
+                )
+            } else {
+                Check::new("Discovery", CheckStatus::Fail, "No devices found").hint(
+                    "Check that this machine is on the same network segment as the Dante devices",
+                )
+            });
+            Some(count)
+        }
+        Err(error) => {
+            checks.push(
+                Check::new(
+                    "Discovery",
+                    CheckStatus::Fail,
+                    format!("Could not start mDNS discovery: {}", error),
+                )
+                .hint("Another program may be holding the mDNS sockets exclusively"),
+            );
+            None
+        }
+    };
+
+    for &(service, browse) in services {
+        checks.push(check_service(service, browse, service_time));
+    }
+
+    checks.push(match discovered {
+        Some(count) if count > 0 => Check::new(
+            "Firewall",
+            CheckStatus::Pass,
+            "mDNS responses are getting through",
+        ),
+        Some(_) if multicast_works => Check::new(
+            "Firewall",
+            CheckStatus::Warn,
+            "Multicast works locally but nothing answered, a firewall is likely dropping inbound mDNS",
+        )
+        .hint("Allow UDP 5353 (mDNS) and the Dante control ports on the Dante interface"),
+        _ => Check::new(
+            "Firewall",
+            CheckStatus::Warn,
+            "Could not tell, fix the checks above first",
+        ),
+    });
+
+    checks
+}
+
+pub fn print_checks(checks: &[Check]) {
+    for check in checks {
+        let label = match check.status {
+            CheckStatus::Pass => "PASS",
+            CheckStatus::Warn => "WARN",
+            CheckStatus::Fail => "FAIL",
+        };
+        println!("[{}] {}: {}", label, check.name, check.detail);
+        if let Some(hint) = check.hint {
+            println!("       hint: {}", hint);
+        }
+    }
+}
+
+/// Prints the checks as one JSON object. The run fails overall only when a check failed, as with
+/// the exit code; warnings still pass.
+pub fn print_checks_json(checks: &[Check]) -> serde_json::Result<()> {
+    let failed = checks.iter().any(|check| check.status == CheckStatus::Fail);
+    let report = Report {
+        checks,
+        overall: if failed { "fail" } else { "pass" },
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+/// Non-loopback IPv4 addresses on this machine, paired with their interface names.
+pub fn local_ipv4_addresses() -> io::Result<Vec<(String, Ipv4Addr)>> {
+    Ok(if_addrs::get_if_addrs()?
+        .into_iter()
+        .filter(|interface| !interface.is_loopback())
+        .filter_map(|interface| match interface.ip() {
+            IpAddr::V4(ip) => Some((interface.name, ip)),
+            IpAddr::V6(_) => None,
+        })
+        .collect())
+}
+
+/// Prints each non-loopback IPv4 interface, whether it could join the mDNS multicast group, and
+/// whether its address is in a range Dante devices normally use, for `debug list-interfaces`.
+pub fn print_interfaces() -> io::Result<()> {
+    let addresses = local_ipv4_addresses()?;
+    if addresses.is_empty() {
+        println!("No IPv4 interface is up");
+        return Ok(());
+    }
+
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    let name_width = addresses
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    for (name, ip) in &addresses {
+        let multicast = if socket.join_multicast_v4(&MDNS_GROUP, ip).is_ok() {
+            "multicast"
+        } else {
+            "no multicast"
+        };
+        let range = if ip.is_link_local() || ip.is_private() {
+            ", likely Dante range"
+        } else {
+            ""
+        };
+        println!("{:<name_width$}  {:<15}  {}{}", name, ip, multicast, range);
+    }
+    Ok(())
+}
+
+fn check_dante_interface(addresses: &[(String, Ipv4Addr)]) -> Check {
+    if addresses.is_empty() {
+        return Check::new(
+            "Dante interface",
+            CheckStatus::Fail,
+            "No IPv4 interface is up",
+        )
+        .hint("Connect this machine to the Dante network");
+    }
+
+    let dante_range: Vec<String> = addresses
+        .iter()
+        .filter(|(_, ip)| ip.is_link_local() || ip.is_private())
+        .map(|(name, ip)| format!("{} ({})", name, ip))
+        .collect();
+
+    if dante_range.is_empty() {
+        Check::new(
+            "Dante interface",
+            CheckStatus::Warn,
+            "No interface has a link-local or private IPv4 address",
+        )
+        .hint("Dante devices normally live on 169.254.0.0/16 or a private range, check which network this machine is on")
+    } else {
+        Check::new("Dante interface", CheckStatus::Pass, dante_range.join(", "))
+    }
+}
+
+fn check_mdns_port() -> Check {
+    match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, MDNS_PORT)) {
+        Ok(_) => Check::new("mDNS port", CheckStatus::Pass, "UDP 5353 can be bound"),
+        Err(error) if error.kind() == io::ErrorKind::AddrInUse => Check::new(
+            "mDNS port",
+            CheckStatus::Warn,
+            "UDP 5353 is already bound by another mDNS responder",
+        )
+        .hint("This is usually fine, but if discovery finds nothing try closing other Dante or Bonjour software"),
+        Err(error) if error.kind() == io::ErrorKind::PermissionDenied => Check::new(
+            "mDNS port",
+            CheckStatus::Fail,
+            "Not allowed to bind UDP 5353",
+        )
+        .hint("Run with enough privileges to bind UDP 5353"),
+        Err(error) => Check::new(
+            "mDNS port",
+            CheckStatus::Fail,
+            format!("Could not bind UDP 5353: {}", error),
+        ),
+    }
+}
+
+fn check_multicast(addresses: &[(String, Ipv4Addr)]) -> Check {
+    let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)) {
+        Ok(socket) => socket,
+        Err(error) => {
+            return Check::new(
+                "Multicast",
+                CheckStatus::Fail,
+                format!("Could not open a UDP socket: {}", error),
+            )
+        }
+    };
+
+    let joined: Vec<&str> = addresses
+        .iter()
+        .filter(|(_, ip)| socket.join_multicast_v4(&MDNS_GROUP, ip).is_ok())
+        .map(|(name, _)| name.as_str())
+        .collect();
+
+    if joined.is_empty() {
+        Check::new(
+            "Multicast",
+            CheckStatus::Fail,
+            "Could not join the mDNS multicast group on any interface",
+        )
+        .hint("Make sure multicast is enabled on the Dante interface")
+    } else {
+        Check::new(
+            "Multicast",
+            CheckStatus::Pass,
+            format!("Joined {} on {}", MDNS_GROUP, joined.join(", ")),
+        )
+    }
+}
+
+/// Browses for one mDNS service with its debug print function, without letting the output through,
+/// and checks whether anything answered.
+fn check_service(service: &'static str, browse: fn(Duration), time: Duration) -> Check {
+    match capture_stdout(|| browse(time)) {
+        Ok(printed) if printed.trim().is_empty() => {
+            Check::new(service, CheckStatus::Warn, "Nothing answered")
+                .hint("Devices that don't offer this service are fine, otherwise check the firewall and the Dante interface")
+        }
+        Ok(printed) => Check::new(
+            service,
+            CheckStatus::Pass,
+            format!("Answered with {} line(s)", printed.lines().count()),
+        ),
+        Err(error) => Check::new(
+            service,
+            CheckStatus::Warn,
+            format!("Could not capture the browse output: {}", error),
+        ),
+    }
+}
+
+fn discover_device_count(time: Duration) -> Result<usize, Box<dyn std::error::Error>> {
+    let device_manager = DanteDeviceManager::new();
+    start_discovery(&device_manager)?;
+    sleep(time);
+    device_manager.stop_discovery();
+
+    Ok(device_manager.get_device_names().len())
+}
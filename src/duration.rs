@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+/// Clap value parser for time arguments like `--time`. Takes a number with an optional `ms`, `s`
+/// or `m` suffix; plain numbers are seconds, as they always have been.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit_seconds) = if let Some(number) = value.strip_suffix("ms") {
+        (number, 0.001)
+    } else if let Some(number) = value.strip_suffix('s') {
+        (number, 1.0)
+    } else if let Some(number) = value.strip_suffix('m') {
+        (number, 60.0)
+    } else {
+        (value, 1.0)
+    };
+
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("{:?} isn't a duration like 500ms, 2s or 1m", value))?;
+    Duration::try_from_secs_f64(number * unit_seconds)
+        .map_err(|_| format!("{:?} is negative or too large", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1m"), Ok(Duration::from_secs(60)));
+        assert_eq!(parse_duration(" 1.5 s "), Ok(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn bare_number_is_seconds() {
+        assert_eq!(parse_duration("3"), Ok(Duration::from_secs(3)));
+        assert_eq!(parse_duration("0.25"), Ok(Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn missing_number() {
+        assert!(parse_duration("ms").is_err());
+        assert!(parse_duration("").is_err());
+    }
+
+    #[test]
+    fn unknown_unit() {
+        assert!(parse_duration("5h").is_err());
+        assert!(parse_duration("5sec").is_err());
+    }
+
+    #[test]
+    fn negative_or_overflowing() {
+        assert!(parse_duration("-1s").is_err());
+        assert!(parse_duration("1e30m").is_err());
+    }
+}
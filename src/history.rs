@@ -0,0 +1,103 @@
+use crate::output::csv_field;
+use chrono::{SecondsFormat, Utc};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+
+/// Layout of the `monitor --history` file.
+///
+/// `csv` writes a `timestamp,device_count,device,present` header once, then on every interval one
+/// row per device seen so far in the run, with `present` set to 1 or 0.
+///
+/// `ndjson` writes one object per interval:
+/// `{"timestamp": "...", "device_count": 2, "present": ["A", "B"], "absent": ["C"]}`, where
+/// `absent` lists devices seen earlier in the run that are currently missing.
+///
+/// Timestamps are RFC 3339 in UTC.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HistoryFormat {
+    Csv,
+    Ndjson,
+}
+
+#[derive(Serialize)]
+struct HistoryRecord<'a> {
+    timestamp: String,
+    device_count: usize,
+    present: Vec<&'a str>,
+    absent: Vec<&'a str>,
+}
+
+/// Appends device presence samples to a history file.
+pub struct HistoryWriter {
+    writer: BufWriter<File>,
+    format: HistoryFormat,
+    preserve_order: bool,
+    seen: Vec<String>,
+}
+
+impl HistoryWriter {
+    /// Opens `path` for appending, writing the CSV header if the file is new. Devices are listed in
+    /// the order they were first seen when `preserve_order` is set, and by name otherwise.
+    pub fn open(path: &str, format: HistoryFormat, preserve_order: bool) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let is_empty = file.metadata()?.len() == 0;
+
+        let mut writer = BufWriter::new(file);
+        if format == HistoryFormat::Csv && is_empty {
+            writeln!(writer, "timestamp,device_count,device,present")?;
+        }
+
+        Ok(Self {
+            writer,
+            format,
+            preserve_order,
+            seen: Vec::new(),
+        })
+    }
+
+    /// Writes one sample for the devices currently present and flushes it to disk.
+    pub fn record(&mut self, present: &[String]) -> io::Result<()> {
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        for device in present {
+            if !self.seen.contains(device) {
+                self.seen.push(device.clone());
+            }
+        }
+        if !self.preserve_order {
+            self.seen.sort();
+        }
+
+        match self.format {
+            HistoryFormat::Csv => {
+                for device in &self.seen {
+                    writeln!(
+                        self.writer,
+                        "{},{},{},{}",
+                        timestamp,
+                        present.len(),
+                        csv_field(device),
+                        u8::from(present.contains(device))
+                    )?;
+                }
+            }
+            HistoryFormat::Ndjson => {
+                let record = HistoryRecord {
+                    timestamp,
+                    device_count: present.len(),
+                    present: present.iter().map(String::as_str).collect(),
+                    absent: self
+                        .seen
+                        .iter()
+                        .filter(|device| !present.contains(device))
+                        .map(String::as_str)
+                        .collect(),
+                };
+                serde_json::to_writer(&mut self.writer, &record)?;
+                writeln!(self.writer)?;
+            }
+        }
+
+        self.writer.flush()
+    }
+}
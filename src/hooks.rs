@@ -0,0 +1,105 @@
+use crate::devices::DeviceChange;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Runs `monitor --on-change` and posts to `--webhook` once the device set has settled after a
+/// change.
+///
+/// Changes are collected until none have been seen for the debounce time, then delivered in one
+/// go, so a device that keeps flapping doesn't fire the hook every interval. Hooks run on their
+/// own thread and never hold up the monitor.
+pub struct ChangeHook {
+    command: Option<String>,
+    webhook: Option<String>,
+    debounce: Duration,
+    pending: Vec<DeviceChange>,
+    last_change: Instant,
+}
+
+impl ChangeHook {
+    /// Creates a hook, or `None` when there's neither a command nor a webhook to deliver to.
+    pub fn new(
+        command: Option<String>,
+        webhook: Option<String>,
+        debounce: Duration,
+    ) -> Option<Self> {
+        if command.is_none() && webhook.is_none() {
+            return None;
+        }
+        Some(Self {
+            command,
+            webhook,
+            debounce,
+            pending: Vec::new(),
+            last_change: Instant::now(),
+        })
+    }
+
+    /// Records one interval's changes, and delivers everything pending once it has settled.
+    pub fn observe(&mut self, changes: &[DeviceChange]) {
+        if !changes.is_empty() {
+            for change in changes {
+                self.pending.retain(|pending| pending.name != change.name);
+                self.pending.push(change.clone());
+            }
+            self.last_change = Instant::now();
+        }
+
+        if !self.pending.is_empty() && self.last_change.elapsed() >= self.debounce {
+            self.fire(std::mem::take(&mut self.pending));
+        }
+    }
+
+    fn fire(&self, changes: Vec<DeviceChange>) {
+        let command = self.command.clone();
+        let webhook = self.webhook.clone();
+        thread::spawn(move || {
+            if let Some(command) = command {
+                run_command(&command, &changes);
+            }
+            if let Some(url) = webhook {
+                post_webhook(&url, &changes);
+            }
+        });
+    }
+}
+
+/// Runs `command` through the shell with the changed device names as its arguments.
+fn run_command(command: &str, changes: &[DeviceChange]) {
+    let names = changes.iter().map(|change| change.name.as_str());
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C").arg(command).args(names);
+        shell
+    } else {
+        // The first argument after the script becomes $0, so names start at $1.
+        let mut shell = Command::new("sh");
+        shell.arg("-c").arg(command).arg("sh").args(names);
+        shell
+    };
+
+    match shell.status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => log::warn!("--on-change command exited with {}", status),
+        Err(error) => log::warn!("Could not run --on-change command: {}", error),
+    }
+}
+
+/// Posts the changes to `url` as the same JSON array `list-devices --since --format json` prints.
+fn post_webhook(url: &str, changes: &[DeviceChange]) {
+    let body = match serde_json::to_string(changes) {
+        Ok(body) => body,
+        Err(error) => {
+            log::warn!("Could not encode the changes for --webhook: {}", error);
+            return;
+        }
+    };
+
+    if let Err(error) = ureq::post(url)
+        .set("Content-Type", "application/json")
+        .send_string(&body)
+    {
+        log::warn!("--webhook request to {} failed: {}", url, error);
+    }
+}
@@ -0,0 +1,159 @@
+use crate::output::OutputError;
+use chrono::{SecondsFormat, Utc};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::sync::Mutex;
+use stderrlog::StdErrLog;
+
+/// Crates whose log records are shown. Everything else, like mDNS internals, stays hidden.
+const MODULES: [&str; 2] = [env!("CARGO_CRATE_NAME"), "dante_control_rs"];
+
+/// How log records are written, for `--log-format`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per record, with timestamp, level, module and message
+    Json,
+}
+
+/// Where records go on stderr.
+enum Stderr {
+    /// stderrlog's usual format, which also takes care of `--quiet`
+    Text(StdErrLog),
+    Json,
+    /// `--quiet` with JSON records
+    Off,
+}
+
+/// Sends each record to stderr, and to `--log-file` when one is given, in the `--log-format`
+/// chosen.
+struct Logger {
+    stderr: Stderr,
+    file: Option<Mutex<BufWriter<File>>>,
+    format: LogFormat,
+    level: LevelFilter,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level && is_logged_module(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if let Stderr::Text(stderr) = &self.stderr {
+            stderr.log(record);
+        }
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        if let Stderr::Json = self.stderr {
+            eprintln!("{}", json_record(record));
+        }
+        if let Some(file) = &self.file {
+            let mut file = file.lock().expect("log file lock poisoned");
+            let _ = match self.format {
+                LogFormat::Text => writeln!(
+                    file,
+                    "{} {} {}: {}",
+                    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                    record.level(),
+                    record.target(),
+                    record.args()
+                ),
+                LogFormat::Json => writeln!(file, "{}", json_record(record)),
+            };
+            let _ = file.flush();
+        }
+    }
+
+    fn flush(&self) {
+        if let Stderr::Text(stderr) = &self.stderr {
+            stderr.flush();
+        }
+        if let Some(file) = &self.file {
+            let _ = file.lock().expect("log file lock poisoned").flush();
+        }
+    }
+}
+
+fn json_record(record: &Record) -> serde_json::Value {
+    serde_json::json!({
+        "timestamp": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+        "level": record.level().as_str(),
+        "module": record.target(),
+        "message": record.args().to_string(),
+    })
+}
+
+/// Installs the global logger. `--quiet` only silences stderr, so a log file keeps recording at
+/// the level `--verbose` asks for. The log file is truncated unless `append` is set.
+pub fn init(
+    quiet: bool,
+    verbosity: usize,
+    format: LogFormat,
+    log_file: Option<&str>,
+    append: bool,
+) -> Result<(), OutputError> {
+    let mut stderr = stderrlog::new();
+    for module in MODULES {
+        stderr.module(module);
+    }
+    stderr.quiet(quiet).verbosity(verbosity);
+
+    if format == LogFormat::Text && log_file.is_none() {
+        stderr.init().expect("Failed to initialize stderrlog");
+        return Ok(());
+    }
+
+    let file = log_file
+        .map(|path| {
+            let file = if append {
+                OpenOptions::new().create(true).append(true).open(path)
+            } else {
+                File::create(path)
+            };
+            file.map(|file| Mutex::new(BufWriter::new(file)))
+                .map_err(|source| OutputError::Open {
+                    path: path.to_string(),
+                    source,
+                })
+        })
+        .transpose()?;
+
+    let stderr = match format {
+        LogFormat::Text => Stderr::Text(stderr),
+        LogFormat::Json if quiet => Stderr::Off,
+        LogFormat::Json => Stderr::Json,
+    };
+    let level = level_for(verbosity);
+    log::set_boxed_logger(Box::new(Logger {
+        stderr,
+        file,
+        format,
+        level,
+    }))
+    .expect("Failed to initialize logging");
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// The level each `-v` selects, matching stderrlog's.
+fn level_for(verbosity: usize) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::Error,
+        1 => LevelFilter::Warn,
+        2 => LevelFilter::Info,
+        3 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+fn is_logged_module(target: &str) -> bool {
+    MODULES.iter().any(|module| {
+        target
+            .strip_prefix(module)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+    })
+}
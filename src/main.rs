@@ -1,16 +1,84 @@
-use ascii::AsAsciiStr;
-use clap::{arg, Parser, Subcommand};
-use dante_control_rs::{print_arc, print_chan, print_cmc, print_dbc, DanteDeviceManager, DanteVersion};
-use std::fs::File;
-use std::io;
-use std::io::BufRead;
-use std::net::Ipv4Addr;
+use ascii::{AsAsciiStr, AsciiStr};
+use cache::DiscoveryCache;
+use capture::capture_stdout;
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
+use clap::builder::PossibleValuesParser;
+use clap::error::ErrorKind;
+use clap::{arg, ArgGroup, CommandFactory, Parser, Subcommand};
+use clap_complete::{generate, Shell};
+use config::parse_with_config;
+use dante_control_rs::{
+    print_arc, print_chan, print_cmc, print_dbc, DanteDeviceManager, DanteVersion,
+};
+use deadline::{parse_deadline, spawn_watchdog};
+use devices::{
+    collect_devices, device_named, diff_devices, diff_intervals, discover_until, find_transmitters,
+    group_devices, has_expected_devices, ip_for_name, load_snapshot, merge_devices, name_for_ip,
+    names_for_ip, parse_ipv4, probe_receivers, reject_ipv6, remove_matching, retain_matching,
+    set_discovery_retries, show_progress, sort_devices, start_discovery, truncate_devices,
+    wait_for_devices, with_countdown, AddressError, ChangeField, DeviceInfo, DiscoveryError,
+    DiscoveryTiming, GroupBy, NameFilter, ProbeError, Progress, ResolveError, SharedDiscovery,
+    SnapshotError, SortOrder, WAIT_POLL_INTERVAL,
+};
+use doctor::{
+    print_checks, print_checks_json, print_interfaces, run_checks, CheckStatus, DoctorError,
+    DoctorFormat, ServiceBrowser,
+};
+use duration::parse_duration;
+use history::{HistoryFormat, HistoryWriter};
+use hooks::ChangeHook;
+use logging::LogFormat;
+use output::{
+    open_output, print_change_sample, print_device_changes, print_device_lines,
+    print_device_sample, print_device_table, print_devices, print_lookup, ColorChoice, DeviceField,
+    IntervalFiles, LookupFormat, MonitorFormat, OutputError, OutputFormat, TableChoice,
+};
+use rate_limit::{parse_rate, RateLimiter};
+use retry::RetryPolicy;
+use std::cmp::Reverse;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::net::{AddrParseError, Ipv4Addr};
+use std::ops::RangeInclusive;
+use std::process::ExitCode;
+use std::slice;
 use std::str::FromStr;
-use std::thread::sleep;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use subscription_file::{
+    dedupe_entries, parse_clear_file, parse_subscription_file, read_subscription_file, FileError,
+    InputFormat, LineError, ParsedFile, SubscriptionEntry, SubscriptionLine,
+};
+
+mod cache;
+mod capture;
+mod config;
+mod deadline;
+mod devices;
+mod doctor;
+mod duration;
+mod history;
+mod hooks;
+mod logging;
+mod output;
+mod rate_limit;
+mod retry;
+mod subscription_file;
+
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0  success
+  1  any other failure, including --deadline being reached or a declined confirmation
+  2  invalid arguments (including no command with --quiet), config file, versions, addresses, names, subscription file lines or JSON subscription files that aren't an array
+  3  discovery couldn't start or didn't find what was asked for in time
+  4  a device rejected a subscription change, or didn't answer within --op-timeout
+  5  an I/O error, such as an unreadable subscription file
+  6  discovery wasn't allowed to open its mDNS socket, or the port was taken by another program";
 
 #[derive(Parser, Debug)]
-#[command(author, version, about = "Command line tool for interacting with dante devices on the local network", long_about = None)]
+#[command(author, version, about = "Command line tool for interacting with dante devices on the local network", long_about = None, after_help = EXIT_CODES_HELP)]
 struct Args {
     #[arg(short, long)]
     quiet: bool,
@@ -18,6 +86,74 @@ struct Args {
     #[arg(short, long, action = clap::ArgAction::Count)]
     verbose: u8,
 
+    /// Cap control operations (subscriptions and clears) to this many per second across the whole invocation, however many of them run in parallel.
+    #[arg(long, value_parser = parse_rate)]
+    limit_rate: Option<f32>,
+
+    /// How to order device listings from list-devices and monitor
+    #[arg(long, value_enum, default_value_t = SortOrder::Name)]
+    sort: SortOrder,
+
+    /// Print devices in the order they were discovered, the same as --sort none
+    #[arg(long, conflicts_with = "sort")]
+    preserve_order: bool,
+
+    /// After discovering devices, print how long discovery took and when devices turned up to stderr
+    #[arg(long)]
+    timing: bool,
+
+    /// Abort with an error if the command hasn't finished by this RFC 3339 time, e.g. 2024-06-01T18:00:00+01:00. This overrides any other wait or timeout.
+    #[arg(long, value_parser = parse_deadline)]
+    deadline: Option<DateTime<FixedOffset>>,
+
+    /// When to color detailed device output. NO_COLOR is respected in auto mode.
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Shorthand for --color never
+    #[arg(long, conflicts_with = "color")]
+    no_color: bool,
+
+    /// Also write log messages to this file, with timestamps, at the level --verbose selects. --quiet doesn't affect it.
+    #[arg(long)]
+    log_file: Option<String>,
+
+    /// Append to --log-file instead of truncating it at the start of each run
+    #[arg(long, requires = "log_file")]
+    log_append: bool,
+
+    /// Format of log messages on stderr and in --log-file. json writes one object per message with timestamp, level, module and message fields.
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Give up on a subscription or clear request that a device hasn't answered after this long, report it as failed and carry on. By default requests wait as long as the device takes.
+    #[arg(long, value_parser = parse_duration)]
+    op_timeout: Option<Duration>,
+
+    /// Keep the names and addresses of discovered devices in this JSON file, and resolve control command lookups from it instead of waiting for discovery. A lookup it can't answer still runs discovery, and the devices found are added to it.
+    #[arg(long, value_name = "PATH")]
+    cache: Option<String>,
+
+    /// How long a device stays in --cache after discovery last saw it
+    #[arg(long, default_value = "10m", value_parser = parse_duration, requires = "cache")]
+    cache_ttl: Duration,
+
+    /// Reject receiver channel indices above this before contacting the device. Dante devices don't report their channel count to this tool, so it has to be given.
+    #[arg(long)]
+    max_channels: Option<u16>,
+
+    /// Retry starting mDNS discovery this many times, waiting twice as long after each failure, before giving up
+    #[arg(long, default_value_t = 0)]
+    discovery_retries: u32,
+
+    /// When a device name looked up through discovery matches no device exactly, accept the one device whose name contains it, ignoring case. More than one such device is an error.
+    #[arg(long)]
+    fuzzy: bool,
+
+    /// Dante version for subscription file lines that leave it out.
+    #[arg(long, env = "DANTE_VERSION", value_parser = PossibleValuesParser::new(SUPPORTED_VERSIONS))]
+    default_version: Option<String>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -25,25 +161,223 @@ struct Args {
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Lists the available dante devices on the local network
+    #[command(group(ArgGroup::new("name_patterns").args(["filter", "exclude"]).multiple(true)))]
     ListDevices {
-        /// Seconds to wait for mDNS to resolve before printing discovered devices
-        #[arg(default_value_t = 5.0, short, long)]
-        time: f32,
+        /// How long to wait for mDNS to resolve before printing discovered devices
+        #[arg(default_value = "5s", short, long, value_parser = parse_duration)]
+        time: Duration,
 
         /// Print detailed info instead of just device names.
         #[arg(short, long)]
         detailed: bool,
+
+        /// Output format. json prints one array of device objects on stdout, with only their names unless --detailed is set, and moves status messages to stderr. The --detailed json output can be saved and passed back in with --since. csv prints a header row and one row per device (name, ip and description with --detailed), and also moves status messages to stderr. yaml prints the same structure as json as a YAML document, with status messages on stderr.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+
+        /// Only print devices that are new or changed compared to a snapshot saved from "list-devices --format json --detailed".
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Fields that count as a change for --since, comma separated. Leave out description when devices put volatile values in it.
+        #[arg(long, value_enum, value_delimiter = ',', default_values_t = [ChangeField::Ip, ChangeField::Description], requires = "since")]
+        change_fields: Vec<ChangeField>,
+
+        /// Only show devices whose name contains this text, ignoring case unless --case-sensitive is set
+        #[arg(long)]
+        filter: Option<String>,
+
+        /// Only show devices whose name contains this text, always ignoring case and never as a regex. A shortcut for when --filter's options aren't wanted.
+        #[arg(long, conflicts_with = "filter")]
+        name_contains: Option<String>,
+
+        /// Hide devices whose name contains this text, even if --filter matches them. Can be given more than once.
+        #[arg(long, value_name = "PATTERN")]
+        exclude: Vec<String>,
+
+        /// Treat --filter and --exclude as regular expressions instead of plain text
+        #[arg(long, requires = "name_patterns")]
+        regex: bool,
+
+        /// Match --filter and --exclude case-sensitively
+        #[arg(long, requires = "name_patterns")]
+        case_sensitive: bool,
+
+        /// Exit with an error when there is nothing to list. Empty results are still printed as a well-formed document.
+        #[arg(long)]
+        fail_if_empty: bool,
+
+        /// Exit with an error, without listing anything, if discovery finds fewer than this many devices
+        #[arg(long, default_value_t = 0)]
+        require_devices: usize,
+
+        /// Stop discovery early once this many devices are known and list only those, noting on stderr that the list was truncated. Bounds the wait and the output on very large networks.
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        max_devices: Option<u32>,
+
+        /// Devices discovery is expected to find. Used by --retry-discovery-until.
+        #[arg(long, default_value_t = 0)]
+        min_devices: usize,
+
+        /// A device name discovery is expected to find, can be repeated. Used by --retry-discovery-until.
+        #[arg(long)]
+        wait_for: Vec<String>,
+
+        /// Stop and restart discovery up to this many times while --min-devices or --wait-for aren't met. Each pass waits the full --time.
+        #[arg(long, default_value_t = 0)]
+        retry_discovery_until: u32,
+
+        /// Run discovery this many times and list every device any pass found, for devices that are slow to answer the first query. Each pass waits the full --time.
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..), conflicts_with = "retry_discovery_until")]
+        repeat: u32,
+
+        /// Stop discovery as soon as a device with exactly this name turns up, and list only that device. --time becomes the longest to wait, and it's an error if the device never shows up.
+        #[arg(long, conflicts_with_all = ["repeat", "since"])]
+        until_found: Option<String>,
+
+        /// Show --detailed text output as an aligned table of names and addresses. --table alone only draws it on a terminal; --table=always draws it when redirected too.
+        #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "auto", requires = "detailed")]
+        table: Option<TableChoice>,
+
+        /// Print each device of --detailed text output on one name=.. ip=.. description=.. line, for grepping
+        #[arg(long, visible_alias = "flatten", requires = "detailed", conflicts_with_all = ["table", "since"])]
+        oneline: bool,
+
+        /// Print --detailed text output in groups, each under a header with its device count
+        #[arg(long, value_enum, requires = "detailed", conflicts_with = "since")]
+        group_by: Option<GroupBy>,
+
+        /// Only include these fields of --detailed output, comma separated and in the order given, in every --format as well as --table and --oneline
+        #[arg(
+            long,
+            value_enum,
+            value_delimiter = ',',
+            requires = "detailed",
+            conflicts_with = "since"
+        )]
+        fields: Option<Vec<DeviceField>>,
+
+        /// Write the device list to this file instead of stdout. Status messages still go to the terminal. The file is replaced in one step once the list is complete, so a reader never sees it half-written.
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Add to the end of the --output file instead of replacing it
+        #[arg(long, requires = "output")]
+        append: bool,
     },
 
-    /// Monitors dante devices and prints device info every <print_interval> seconds.
+    /// Monitors dante devices and prints device info every <print_interval>.
+    #[command(group(ArgGroup::new("name_patterns").args(["filter", "exclude"]).multiple(true)))]
     Monitor {
         /// Interval to print values to stdout
-        #[arg(default_value_t = 2.0, short, long)]
-        print_interval: f32,
+        #[arg(default_value = "2s", short, long, value_parser = parse_duration)]
+        print_interval: Duration,
+
+        /// Print on wall-clock multiples of --print-interval since the Unix epoch, like the top of every minute with 1m, so monitors on different machines line up. A print that would be late is skipped to the next boundary instead.
+        #[arg(long)]
+        align: bool,
 
         /// Print detailed info instead of just device names.
         #[arg(short, long)]
         detailed: bool,
+
+        /// Print each device of --detailed output on one name=.. ip=.. description=.. line, for grepping
+        #[arg(long, visible_alias = "flatten", requires = "detailed")]
+        oneline: bool,
+
+        /// Only include these fields of --detailed output, comma separated and in the order given, in text, --oneline and ndjson output
+        #[arg(
+            long,
+            value_enum,
+            value_delimiter = ',',
+            requires = "detailed",
+            conflicts_with = "changes_only"
+        )]
+        fields: Option<Vec<DeviceField>>,
+
+        /// Also append a time-series of device presence to this file on every interval
+        #[arg(long)]
+        history: Option<String>,
+
+        /// Layout of the --history file
+        #[arg(long, value_enum, default_value_t = HistoryFormat::Ndjson)]
+        history_format: HistoryFormat,
+
+        /// Only show devices whose name contains this text, ignoring case unless --case-sensitive is set
+        #[arg(long)]
+        filter: Option<String>,
+
+        /// Only show devices whose name contains this text, always ignoring case and never as a regex. A shortcut for when --filter's options aren't wanted.
+        #[arg(long, conflicts_with = "filter")]
+        name_contains: Option<String>,
+
+        /// Hide devices whose name contains this text, even if --filter matches them. Can be given more than once.
+        #[arg(long, value_name = "PATTERN")]
+        exclude: Vec<String>,
+
+        /// Treat --filter and --exclude as regular expressions instead of plain text
+        #[arg(long, requires = "name_patterns")]
+        regex: bool,
+
+        /// Match --filter and --exclude case-sensitively
+        #[arg(long, requires = "name_patterns")]
+        case_sensitive: bool,
+
+        /// Append device info to this file instead of printing it, with each interval headed by a timestamp.
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Start the --output file afresh instead of appending to what earlier runs left in it
+        #[arg(long, requires = "output")]
+        truncate: bool,
+
+        /// Write each interval to its own timestamped file in this directory instead, creating it if needed
+        #[arg(long, value_name = "DIR", conflicts_with = "output")]
+        output_dir: Option<String>,
+
+        /// Only keep this many of the newest interval files in --output-dir, deleting older ones
+        #[arg(long, requires = "output_dir", value_parser = clap::value_parser!(u32).range(1..))]
+        keep: Option<u32>,
+
+        /// Stop after printing this many times. 0 keeps monitoring until interrupted.
+        #[arg(short, long, default_value_t = 0)]
+        count: u32,
+
+        /// Exit with an error as soon as an interval finds fewer than this many devices
+        #[arg(long, default_value_t = 0)]
+        require_devices: usize,
+
+        /// Only show the first this many devices discovery knows about each interval, noting on stderr when there were more
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        max_devices: Option<u32>,
+
+        /// Output format. ndjson writes one JSON object per interval and moves the banner and separators to stderr.
+        #[arg(long, value_enum, default_value_t = MonitorFormat::Text)]
+        format: MonitorFormat,
+
+        /// Only print devices that appeared (+), disappeared (-) or changed (~) in one of the --change-fields since the last interval, and skip intervals where nothing did
+        #[arg(long)]
+        changes_only: bool,
+
+        /// Fields that count as a change for --changes-only and the hooks, comma separated. Leave out description when devices put volatile values in it.
+        #[arg(long, value_enum, value_delimiter = ',', default_values_t = [ChangeField::Ip, ChangeField::Description])]
+        change_fields: Vec<ChangeField>,
+
+        /// Run this shell command when devices change, with the changed device names as its arguments
+        #[arg(long, value_name = "CMD")]
+        on_change: Option<String>,
+
+        /// POST the changed devices as JSON to this URL when devices change
+        #[arg(long, value_name = "URL")]
+        webhook: Option<String>,
+
+        /// Check for device changes this often between prints, so --changes-only and the hooks catch changes as they happen while printing stays on the slower cadence. Defaults to the print interval.
+        #[arg(long, value_parser = parse_duration)]
+        poll_interval: Option<Duration>,
+
+        /// How long devices must stay unchanged before --on-change or --webhook fires, so a flapping device doesn't fire every interval
+        #[arg(long, value_parser = parse_duration, default_value = "5s")]
+        debounce: Duration,
     },
 
     /// Command for controlling dante devices.
@@ -53,48 +387,257 @@ enum Commands {
     /// Debug commands (mostly for mDNS).
     #[command(subcommand)]
     Debug(DebugCommands),
+
+    /// Looks up the IPv4 address of a device by name, or with --ip the name of a device by address.
+    Resolve {
+        /// Name of the dante device to look up, or its address with --ip. - reads them from stdin instead, like --stdin.
+        #[arg(required_unless_present = "stdin")]
+        device_name: Option<String>,
+
+        /// Read device names from stdin, one per line, and print a name<TAB>ip line for each, or name<TAB>NOT_FOUND, and exit with an error if any weren't found. Discovery runs once for all of them.
+        #[arg(long, conflicts_with = "device_name")]
+        stdin: bool,
+
+        /// Look up by IPv4 address instead, and print the name of every device found at it, or NOT_FOUND. With --stdin each line is an address, and an ip<TAB>name line is printed for each device.
+        #[arg(long, visible_alias = "reverse")]
+        ip: bool,
+
+        /// Longest to wait for the device to be discovered
+        #[arg(default_value = "5s", short, long, value_parser = parse_duration)]
+        time: Duration,
+
+        /// Output format. env prints DANTE_DEVICE_IP=<ip> for use with eval or source
+        #[arg(long, value_enum, default_value_t = LookupFormat::Text)]
+        format: LookupFormat,
+    },
+
+    /// Checks the local machine for common problems that stop Dante devices from being found.
+    Doctor {
+        /// How long to spend on a trial discovery
+        #[arg(default_value = "3s", short, long, value_parser = parse_duration)]
+        time: Duration,
+
+        /// How long to browse for each of the four _netaudio mDNS services
+        #[arg(default_value = "1s", long, value_parser = parse_duration)]
+        service_time: Duration,
+
+        /// Output format. json prints one object with a name, status and detail for each check, and an overall pass or fail that matches the exit code.
+        #[arg(long, value_enum, default_value_t = DoctorFormat::Text)]
+        format: DoctorFormat,
+    },
+
+    /// Prints the CLI and dante-control-rs versions and the Dante protocol versions supported, for bug reports.
+    Info,
+
+    /// Prints a shell completion script to stdout.
+    Completions {
+        /// Shell to generate completions for
+        shell: Shell,
+    },
 }
 
 #[derive(Subcommand, Debug)]
 enum ControlCommands {
     /// Make subscription
+    #[command(
+        override_usage = "dante-cli control make [OPTIONS] [VERSION] <TRANSMITTER_NAME> <TRANSMITTER_CHANNEL_NAME> <RECEIVER_IP_STRING> <RECEIVER_CHANNEL_INDEX>"
+    )]
     Make {
-        /// Dante version to use. Possible values are "4.4.1.3" and "4.2.1.3"
+        /// Dante version to use. Supported versions are "4.4.1.3" and "4.2.1.3". Can be left out to use --default-version.
         version: String,
 
-        /// Name of the dante device to transmit the new subscription
+        /// Name of the dante device to transmit the new subscription. An IPv4 address is looked up through discovery instead.
         transmitter_name: String,
 
         /// Channel id of the dante device to transmit the new subscription
         transmitter_channel_name: String,
 
-        /// Ip of the dante device to receive the new subscription
-        receiver_ip_string: String,
+        /// Ip of the dante device to receive the new subscription. A device name is resolved to its IPv4 address through discovery instead. IPv6 isn't supported. Separate several receivers with commas to subscribe each of them.
+        #[arg(value_delimiter = ',', num_args = 1, required = true)]
+        receiver_ip_string: Vec<String>,
 
-        /// Channel id of the dante device to receive the new subscription
-        receiver_channel_index: u16,
+        /// Channel id of the dante device to receive the new subscription. With several receivers, give one index for all of them or a comma-separated index per receiver.
+        #[arg(value_delimiter = ',', num_args = 1)]
+        receiver_channel_index: Vec<u16>,
+
+        /// Longest to wait for discovery when the transmitter is given by IP address, the receivers by name, or when probing. Discovery runs once and every lookup shares it, so this bounds all of them together.
+        #[arg(default_value = "3s", long, visible_alias = "resolve-time", value_parser = parse_duration)]
+        discovery_time: Duration,
+
+        /// Only check that the receiver answers discovery, without changing anything.
+        #[arg(long)]
+        probe_only: bool,
+
+        /// Check through discovery that the transmitter is on the network before subscribing. Its channels can't be listed, so only the device is checked.
+        #[arg(long, visible_alias = "transmitter-must-exist")]
+        verify_transmitter: bool,
+
+        /// Send the subscription straight from the arguments without ever starting discovery. The transmitter has to be given by name, used as it is even with --fuzzy, and every receiver by IPv4 address; anything that would need a lookup is an error.
+        #[arg(long, conflicts_with_all = ["probe_only", "verify_transmitter"])]
+        no_discovery: bool,
+
+        /// Retry a failed subscription this many times before giving up
+        #[arg(long, default_value_t = 0)]
+        retry: u32,
+
+        /// How long to wait between retries
+        #[arg(long, default_value = "1s", value_parser = parse_duration)]
+        retry_delay: Duration,
     },
 
     /// Make subscription
+    #[command(
+        override_usage = "dante-cli control clear [OPTIONS] [VERSION] <RECEIVER_IP_STRING> <RECEIVER_CHANNEL_INDEX>"
+    )]
     Clear {
-        /// Dante version to use. Possible values are "4.4.1.3" and "4.2.1.3"
+        /// Dante version to use. Supported versions are "4.4.1.3" and "4.2.1.3". Can be left out to use --default-version.
         version: String,
 
-        /// Ip of the dante device to receive the new subscription
+        /// Ip of the dante device to receive the new subscription. A device name is resolved to its IPv4 address through discovery instead. IPv6 isn't supported.
         receiver_ip_string: String,
 
         /// Channel id of the dante device to receive the new subscription
-        receiver_channel_index: u16,
+        receiver_channel_index: Option<u16>,
+
+        /// Longest to wait for discovery when the receiver is given by name, or when probing
+        #[arg(default_value = "3s", long, visible_alias = "resolve-time", value_parser = parse_duration)]
+        discovery_time: Duration,
+
+        /// Only check that the receiver answers discovery, without changing anything.
+        #[arg(long)]
+        probe_only: bool,
+
+        /// Don't ask for confirmation first. Without a terminal on stdin it's never asked for.
+        #[arg(short, long)]
+        yes: bool,
+    },
+
+    /// Clear every subscription on a receiver. Receiver channels can't be enumerated, so each index in --range is cleared in turn, and indices that were already empty are skipped over.
+    #[command(
+        override_usage = "dante-cli control clear-all [OPTIONS] [VERSION] <RECEIVER_IP_STRING>"
+    )]
+    ClearAll {
+        /// Dante version to use. Supported versions are "4.4.1.3" and "4.2.1.3". Can be left out to use --default-version.
+        version: String,
+
+        /// Ip of the dante device to clear. A device name is resolved to its IPv4 address through discovery instead. IPv6 isn't supported.
+        receiver_ip_string: Option<String>,
+
+        /// Receiver channel indices to clear, as START-END or a single index
+        #[arg(long, value_parser = parse_channel_range, default_value = "1-64")]
+        range: RangeInclusive<u16>,
+
+        /// Longest to wait for discovery when the receiver is given by name
+        #[arg(default_value = "3s", long, visible_alias = "resolve-time", value_parser = parse_duration)]
+        discovery_time: Duration,
+
+        /// Don't ask for confirmation first. Without a terminal on stdin it's never asked for.
+        #[arg(short, long)]
+        yes: bool,
     },
 
-    /// Make a series of subscriptions as specified in plaintext from a file, where each line is another subscription and looks like this: DanteVersion|TransmitterChannelName@TransmitterDeviceName:ReceiverChannelIndex@ReceiverIp. Note the receiver using an index instead of a channel name. Clear the subscription by only providing the version, receiver ip, and channel index: DanteVersion|receiver_index@receiver_ip
+    /// Make a series of subscriptions as specified in plaintext from a file, where each line is another subscription and looks like this: DanteVersion|TransmitterChannelName@TransmitterDeviceName:ReceiverChannelIndex@ReceiverIp. Note the receiver using an index instead of a channel name. Clear the subscription by only providing the version, receiver ip, and channel index: DanteVersion|receiver_index@receiver_ip. Names containing @, : or | can be wrapped in double quotes, like "Tx@Special"@Device:3@10.0.0.5. End a line with " @priority N" to apply it before lines with a lower priority; lines without one have priority 0
     FromFile {
-        /// Path of file to read from.
+        /// Path of file to read from, or - to read from stdin.
+        file_path: String,
+
+        /// How long to wait between each subscription.
+        #[arg(default_value = "10ms", short, long, value_parser = parse_duration)]
+        time: Duration,
+
+        /// Longest to wait for discovery when probing or verifying transmitters
+        #[arg(default_value = "3s", long, value_parser = parse_duration)]
+        discovery_time: Duration,
+
+        /// Check through discovery that every transmitter in the file is on the network before applying anything, and fail the lines whose transmitter isn't. Discovery runs once for the whole file.
+        #[arg(long, visible_alias = "transmitter-must-exist", conflicts_with_all = ["dry_run", "probe_only"])]
+        verify_transmitter: bool,
+
+        /// Only check that every receiver in the file answers discovery, without changing anything.
+        #[arg(long)]
+        probe_only: bool,
+
+        /// Parse and validate the whole file and print what each line would do, without touching any device.
+        #[arg(long, conflicts_with = "probe_only")]
+        dry_run: bool,
+
+        /// Stop at the first line that fails to parse or apply, instead of carrying on and summarising the failures at the end.
+        #[arg(long)]
+        fail_fast: bool,
+
+        /// Retry each failed line this many times before giving up
+        #[arg(long, default_value_t = 0)]
+        retry: u32,
+
+        /// How long to wait between retries
+        #[arg(long, default_value = "1s", value_parser = parse_duration)]
+        retry_delay: Duration,
+
+        /// After applying the file, write a JSON report to this path: total, succeeded, failed, skipped and duplicates counts, and a failures array with each line number, line and error.
+        #[arg(long, conflicts_with_all = ["dry_run", "probe_only"])]
+        summary_json: Option<String>,
+
+        /// Format of the file. Defaults to json for files ending in .json, and text otherwise.
+        #[arg(long, value_enum)]
+        input_format: Option<InputFormat>,
+
+        /// When several lines change the same receiver channel, apply only the last of them
+        #[arg(long)]
+        dedupe: bool,
+
+        /// Succeed with a warning when the file has no subscription lines at all, instead of failing
+        #[arg(long)]
+        allow_empty: bool,
+
+        /// Replace ${VAR} references in each line with environment variables before parsing it, failing lines that reference an unset variable
+        #[arg(long)]
+        expand_env: bool,
+
+        /// After applying the file, keep watching it and re-apply it each time it's saved, printing which lines changed and sending only those. Lines removed from the file aren't cleared. Stop with Ctrl-C.
+        #[arg(long, conflicts_with_all = ["dry_run", "probe_only", "verify_transmitter", "fail_fast"])]
+        watch: bool,
+
+        /// Apply lines on this many worker threads. Lines for the same receiver are still applied one at a time and in order, and every line of one @priority is done before any line of a lower one starts.
+        #[arg(short, long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        jobs: u32,
+
+        /// Before applying anything, check every transmitter device and channel name in the file for characters that can't be sent as ASCII, list each offending line with those characters, and stop if there are any. --dry-run reports these lines along with other problems.
+        #[arg(long, conflicts_with_all = ["dry_run", "probe_only"])]
+        check_encoding: bool,
+
+        /// Apply the file even though --check-encoding found non-ASCII names. Those lines fail as usual.
+        #[arg(long, requires = "check_encoding")]
+        force: bool,
+    },
+
+    /// Clear a list of receiver channels from a file, where each line is just ReceiverChannelIndex@ReceiverIp. Blank lines and # comments are skipped.
+    BulkClear {
+        /// Path of file to read from, or - to read from stdin.
         file_path: String,
 
-        /// Seconds to wait between each subscription.
-        #[arg(default_value_t = 0.01, short, long)]
-        time: f32,
+        /// Dante version for every line. Defaults to --default-version. Supported versions are "4.4.1.3" and "4.2.1.3"
+        #[arg(long, value_parser = PossibleValuesParser::new(SUPPORTED_VERSIONS))]
+        version: Option<String>,
+
+        /// How long to wait between each clear.
+        #[arg(default_value = "10ms", short, long, value_parser = parse_duration)]
+        time: Duration,
+
+        /// Stop at the first line that fails to parse or apply, instead of carrying on and summarising the failures at the end.
+        #[arg(long)]
+        fail_fast: bool,
+
+        /// Retry each failed line this many times before giving up
+        #[arg(long, default_value_t = 0)]
+        retry: u32,
+
+        /// How long to wait between retries
+        #[arg(long, default_value = "1s", value_parser = parse_duration)]
+        retry_delay: Duration,
+
+        /// Don't ask for confirmation first. Without a terminal on stdin it's never asked for.
+        #[arg(short, long)]
+        yes: bool,
     },
 }
 
@@ -102,224 +645,2501 @@ enum ControlCommands {
 enum DebugCommands {
     /// Lists information about mDNS discovery on the "_netaudio-cmc._udp.local." address.
     PrintCMC {
-        /// Seconds to wait for mDNS to resolve
-        #[arg(default_value_t = 2.0, short, long)]
-        time: f32,
+        /// How long to wait for mDNS to resolve
+        #[arg(default_value = "2s", short, long, value_parser = parse_duration)]
+        time: Duration,
+
+        /// Also write the output to this file, under a header with the time, service type and wait
+        #[arg(short, long)]
+        output: Option<String>,
     },
 
     /// Lists information about mDNS discovery on the "_netaudio-dbc._udp.local." address.
     PrintDBC {
-        /// Seconds to wait for mDNS to resolve
-        #[arg(default_value_t = 2.0, short, long)]
-        time: f32,
+        /// How long to wait for mDNS to resolve
+        #[arg(default_value = "2s", short, long, value_parser = parse_duration)]
+        time: Duration,
+
+        /// Also write the output to this file, under a header with the time, service type and wait
+        #[arg(short, long)]
+        output: Option<String>,
     },
 
     /// Lists information about mDNS discovery on the "_netaudio-arc._udp.local." address.
     PrintARC {
-        /// Seconds to wait for mDNS to resolve
-        #[arg(default_value_t = 2.0, short, long)]
-        time: f32,
+        /// How long to wait for mDNS to resolve
+        #[arg(default_value = "2s", short, long, value_parser = parse_duration)]
+        time: Duration,
+
+        /// Also write the output to this file, under a header with the time, service type and wait
+        #[arg(short, long)]
+        output: Option<String>,
     },
 
     /// Lists information about mDNS discovery on the "_netaudio-chan._udp.local." address.
     PrintCHAN {
-        /// Seconds to wait for mDNS to resolve
-        #[arg(default_value_t = 2.0, short, long)]
-        time: f32,
+        /// How long to wait for mDNS to resolve
+        #[arg(default_value = "2s", short, long, value_parser = parse_duration)]
+        time: Duration,
+
+        /// Also write the output to this file, under a header with the time, service type and wait
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Runs PrintCMC, PrintDBC, PrintARC and PrintCHAN one after another, each under its own header.
+    PrintAll {
+        /// How long to wait for mDNS to resolve, for each service type
+        #[arg(default_value = "2s", short, long, value_parser = parse_duration)]
+        time: Duration,
+
+        /// Also write the output to this file, under a header with the time, service type and wait
+        #[arg(short, long)]
+        output: Option<String>,
     },
+
+    /// Lists this machine's IPv4 network interfaces, whether each one can join the mDNS multicast group, and which have an address in a range Dante devices normally use.
+    ListInterfaces,
 }
 
+/// The mDNS service types the debug print commands cover, in `print-all` order.
+static DEBUG_SERVICES: [ServiceBrowser; 4] = [
+    ("_netaudio-cmc._udp.local.", print_cmc),
+    ("_netaudio-dbc._udp.local.", print_dbc),
+    ("_netaudio-arc._udp.local.", print_arc),
+    ("_netaudio-chan._udp.local.", print_chan),
+];
+
 #[derive(thiserror::Error, Debug)]
-pub enum SubscriptionError {
-    #[error("Couldn't parse version argument into a valid Dante Version.")]
-    VersionParse,
+pub enum ListingError {
+    #[error("Nothing to list")]
+    Empty,
+    #[error("Discovered {found} device(s), but at least {required} are required")]
+    TooFewDevices { found: usize, required: usize },
+}
+
+fn check_device_count(found: usize, required: usize) -> Result<(), ListingError> {
+    if found < required {
+        return Err(ListingError::TooFewDevices { found, required });
+    }
+    Ok(())
 }
 
+/// Dante versions `DanteVersion::from_string` accepts.
+const SUPPORTED_VERSIONS: [&str; 2] = ["4.4.1.3", "4.2.1.3"];
 
 #[derive(thiserror::Error, Debug)]
-pub enum ParsingError {
-    #[error("Could not properly detect : between the transmitting and receiving devices")]
-    TxRxDelimiter,
-    #[error("Could not properly detect @ between the transmitting channel and device name")]
-    TxDelimiter,
-    #[error("Could not properly detect @ between the receiving channel index and device name")]
-    RxDelimiter,
-    #[error("Could not properly detect | between the version and tx/rx devices")]
-    VersionDelimiter,
-    #[error("Could not parse the receiving channel index into an integer")]
-    RxChanIndexParse,
-}
-
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
-
-    stderrlog::new()
-        .module(module_path!())
-        .module("dante_control_rs")
-        .quiet(args.quiet)
-        .verbosity(args.verbose as usize)
-        .init()
-        .expect("Failed to initialize stderrlog");
+pub enum SubscriptionError {
+    #[error("{0} line(s) of the subscription file are invalid")]
+    InvalidLines(usize),
+    #[error("{0} line(s) of the subscription file failed")]
+    FailedLines(usize),
+    #[error("{0} receiver(s) failed")]
+    FailedReceivers(usize),
+    #[error("The device rejected the change: {0}")]
+    Control(String),
+    #[error("Receiver channel {index} is out of range, the receiver has only {max} channels (--max-channels)")]
+    ChannelOutOfRange { index: u16, max: u16 },
+    #[error("No Dante version given. Pass --version or --default-version")]
+    MissingVersion,
+    #[error("Couldn't parse version {0:?} into a valid Dante Version. Supported versions are {}", SUPPORTED_VERSIONS.join(", "))]
+    VersionParse(String),
+    #[error("No subscriptions found in {0}. Pass --allow-empty if that's expected")]
+    EmptyFile(String),
+    #[error("Cancelled, nothing was changed")]
+    Cancelled,
+    #[error("The device didn't answer within --op-timeout ({0:.1?})")]
+    TimedOut(Duration),
+}
 
-    match &args.command {
-        Some(Commands::ListDevices { time, detailed }) => {
-            let device_manager = DanteDeviceManager::new();
-            device_manager.start_discovery()?;
+#[derive(thiserror::Error, Debug)]
+#[error("{kind} {name:?} has a non-ASCII character at byte {position}, and Dante control only sends ASCII names. Check the name configured on the device.")]
+pub struct NonAsciiName {
+    kind: &'static str,
+    name: String,
+    position: usize,
+}
 
-            if !args.quiet {
-                println!("Discovering Devices...");
-            }
+/// Converts a transmitter device or channel name for the control protocol, naming the first
+/// character it can't send.
+fn ascii_name<'a>(kind: &'static str, name: &'a str) -> Result<&'a AsciiStr, NonAsciiName> {
+    name.as_ascii_str().map_err(|error| NonAsciiName {
+        kind,
+        name: name.to_string(),
+        position: error.valid_up_to(),
+    })
+}
 
-            sleep(Duration::from_secs_f32(*time));
+/// The Dante version a single control command uses. Its leading version positional can be left
+/// out for `--default-version`, and then clap, filling positionals in order, puts every argument one
+/// place early and leaves the `last` one missing. A `first` positional that is a known version
+/// means the last argument really was forgotten.
+fn command_version(
+    first: &str,
+    last_missing: bool,
+    last: &str,
+    default_version: Option<&str>,
+) -> Result<String, SubscriptionError> {
+    if !last_missing {
+        return Ok(first.to_string());
+    }
+    if DanteVersion::from_string(first).is_some() {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                format!(
+                    "the following required argument was not provided: <{}>",
+                    last
+                ),
+            )
+            .exit()
+    }
+    default_version
+        .map(str::to_string)
+        .ok_or(SubscriptionError::MissingVersion)
+}
 
-            device_manager.stop_discovery();
+/// Parses a receiver channel index that clap couldn't check, because it landed in the receiver's
+/// positional when the version was left out.
+fn channel_index_arg(value: &str) -> u16 {
+    value.trim().parse().unwrap_or_else(|_| {
+        Args::command()
+            .error(
+                ErrorKind::InvalidValue,
+                format!("invalid receiver channel index {:?}", value),
+            )
+            .exit()
+    })
+}
 
-            if !args.quiet {
-                println!("Devices Found:\n");
-            }
+/// Checks a receiver channel index against `--max-channels`, when it's given.
+fn check_channel(index: u16, max_channels: Option<u16>) -> Result<(), SubscriptionError> {
+    match max_channels {
+        Some(max) if index > max => Err(SubscriptionError::ChannelOutOfRange { index, max }),
+        _ => Ok(()),
+    }
+}
 
-            if !*detailed {
-                for device_name in device_manager.get_device_names() {
-                    println!("{}", device_name);
-                }
-            } else {
-                for device_info in device_manager.get_device_descriptions() {
-                    println!("{}", device_info);
-                    println!("---------------------------------");
-                }
-            }
+fn control_error(error: impl std::fmt::Display) -> SubscriptionError {
+    SubscriptionError::Control(error.to_string())
+}
+
+fn parse_version(version: &str) -> Result<DanteVersion, SubscriptionError> {
+    DanteVersion::from_string(version)
+        .ok_or_else(|| SubscriptionError::VersionParse(version.to_string()))
+}
+
+/// Clap value parser for `control clear-all --range`. A single index is a range of one.
+fn parse_channel_range(value: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = value.split_once('-').unwrap_or((value, value));
+    let parse_index = |index: &str| {
+        index
+            .trim()
+            .parse::<u16>()
+            .map_err(|_| format!("{:?} isn't a range like 1-64", value))
+    };
+    let (start, end) = (parse_index(start)?, parse_index(end)?);
+    if start == 0 {
+        return Err("channel indices start at 1".to_string());
+    }
+    if start > end {
+        return Err(format!("range start {} is after its end {}", start, end));
+    }
+    Ok(start..=end)
+}
+
+/// Checks everything about a subscription file action that can be checked without a device.
+fn validate_action(
+    action: &SubscriptionLine,
+    max_channels: Option<u16>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    check_channel(action.receiver().1, max_channels)?;
+    match action {
+        SubscriptionLine::Make {
+            version,
+            transmitter_name,
+            transmitter_channel_name,
+            receiver_ip,
+            ..
+        } => {
+            parse_version(version)?;
+            parse_ipv4(receiver_ip)?;
+            ascii_name("Transmitter name", transmitter_name)?;
+            ascii_name("Transmitter channel name", transmitter_channel_name)?;
         }
-        Some(Commands::Monitor {
-            print_interval,
-            detailed,
-        }) => {
-            let device_manager = DanteDeviceManager::new();
-            device_manager.start_discovery()?;
+        SubscriptionLine::Clear {
+            version,
+            receiver_ip,
+            ..
+        } => {
+            parse_version(version)?;
+            parse_ipv4(receiver_ip)?;
+        }
+    }
+    Ok(())
+}
 
-            if !args.quiet {
-                println!("Starting monitoring");
-            }
+type EntryResult = (
+    SubscriptionEntry,
+    Result<(), Box<dyn std::error::Error + Send + Sync>>,
+);
 
-            loop {
-                sleep(Duration::from_secs_f32(*print_interval));
-                println!("=================================");
-                if !*detailed {
-                    for device_name in device_manager.get_device_names() {
-                        println!("{}", device_name);
-                    }
-                } else {
-                    for device_info in device_manager.get_device_descriptions() {
-                        println!("{}", device_info);
-                        println!("---------------------------------");
-                    }
-                }
-            }
+/// A line that failed to parse or apply, as `(line_number, text, error)`.
+type LineFailure = (usize, String, Box<dyn std::error::Error + Send + Sync>);
+
+/// Counts about a subscription file that its results alone don't show.
+struct FileStats {
+    /// Lines read, including skipped ones.
+    total: usize,
+    /// Blank and comment lines.
+    skipped: usize,
+    /// How many lines `--dedupe` collapsed, when it was given.
+    duplicates: Option<usize>,
+}
+
+/// Prints the summary of a subscription file run, along with every line that failed, and turns
+/// any failure into the command's error. With `summary_json` the same summary is also written to
+/// that file as JSON.
+fn report_file_results(
+    results: Vec<EntryResult>,
+    mut failures: Vec<LineFailure>,
+    stats: &FileStats,
+    fail_fast: bool,
+    quiet: bool,
+    summary_json: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut succeeded = 0;
+    for (entry, result) in results {
+        match result {
+            Ok(()) => succeeded += 1,
+            Err(error) => failures.push((entry.line_number, entry.text, error)),
         }
-        Some(Commands::Debug(debug_command)) => match debug_command {
-            DebugCommands::PrintCMC { time } => {
-                print_cmc(Duration::from_secs_f32(*time));
-            }
-            DebugCommands::PrintDBC { time } => {
-                print_dbc(Duration::from_secs_f32(*time));
-            }
-            DebugCommands::PrintARC { time } => {
-                print_arc(Duration::from_secs_f32(*time));
-            }
-            DebugCommands::PrintCHAN { time } => {
-                print_chan(Duration::from_secs_f32(*time));
-            }
-        },
-        Some(Commands::Control(control_command)) => match control_command {
-            ControlCommands::Make {
-                version,
-                transmitter_name,
-                transmitter_channel_name,
-                receiver_ip_string,
-                receiver_channel_index,
-            } => {
-                let version = DanteVersion::from_string(version).ok_or(SubscriptionError::VersionParse)?;
+    }
 
+    failures.sort_by_key(|(line_number, _, _)| *line_number);
+    if let Some(path) = summary_json {
+        let failed: Vec<serde_json::Value> = failures
+            .iter()
+            .map(|(line_number, text, error)| {
+                serde_json::json!({ "line": line_number, "text": text, "error": error.to_string() })
+            })
+            .collect();
+        let summary = serde_json::json!({
+            "total": stats.total,
+            "succeeded": succeeded,
+            "failed": failures.len(),
+            "skipped": stats.skipped,
+            "duplicates": stats.duplicates.unwrap_or(0),
+            "failures": failed,
+        });
+        let mut out = open_output(Some(path), false)?;
+        writeln!(out, "{}", serde_json::to_string_pretty(&summary)?)?;
+        out.finish()?;
+    }
+    if fail_fast && !failures.is_empty() {
+        let (_, _, error) = failures.remove(0);
+        return Err(error);
+    }
+    if !quiet {
+        match stats.duplicates {
+            Some(duplicates) => eprintln!(
+                "{} line(s) succeeded, {} failed, {} duplicate(s) collapsed",
+                succeeded,
+                failures.len(),
+                duplicates
+            ),
+            None => eprintln!("{} line(s) succeeded, {} failed", succeeded, failures.len()),
+        }
+    }
+    for (line_number, text, error) in &failures {
+        eprintln!("line {}: {}: {}", line_number, error, text);
+    }
+    if !failures.is_empty() {
+        return Err(SubscriptionError::FailedLines(failures.len()).into());
+    }
+    Ok(())
+}
 
-                let receiver_ip = Ipv4Addr::from_str(receiver_ip_string)?;
-                let transmitter_name_ascii = transmitter_name.as_ascii_str()?;
-                let transmitter_channel_name_ascii = transmitter_channel_name.as_ascii_str()?;
+/// Splits parsed lines into the entries to apply and the lines that failed to parse or name a
+/// channel past `max_channels`. With `fail_fast` the first bad line is returned as the error
+/// instead.
+fn split_parsed(
+    parsed: ParsedFile,
+    fail_fast: bool,
+    max_channels: Option<u16>,
+) -> Result<(Vec<SubscriptionEntry>, Vec<LineFailure>, FileStats), Box<dyn std::error::Error>> {
+    let stats = FileStats {
+        total: parsed.entries.len() + parsed.skipped,
+        skipped: parsed.skipped,
+        duplicates: None,
+    };
+    let mut entries = Vec::new();
+    let mut failures: Vec<LineFailure> = Vec::new();
+    for entry in parsed.entries {
+        match entry {
+            Ok(entry) => match check_channel(entry.action.receiver().1, max_channels) {
+                Ok(()) => entries.push(entry),
+                Err(error) if fail_fast => return Err(error.into()),
+                Err(error) => failures.push((entry.line_number, entry.text, error.into())),
+            },
+            Err(error) if fail_fast => return Err(error.into()),
+            Err(LineError {
+                line,
+                content,
+                error,
+            }) => failures.push((line, content, error.into())),
+        }
+    }
+    Ok((entries, failures, stats))
+}
 
-                let mut device_manager = DanteDeviceManager::new();
+/// Prints every line whose transmitter device or channel name can't be sent as ASCII, with the
+/// characters at fault, for `--check-encoding`. Returns how many lines were printed.
+fn report_non_ascii_names(entries: &[SubscriptionEntry]) -> usize {
+    let mut lines = 0;
+    for entry in entries {
+        let SubscriptionLine::Make {
+            transmitter_name,
+            transmitter_channel_name,
+            ..
+        } = &entry.action
+        else {
+            continue;
+        };
 
-                device_manager.make_subscription(
-                    &version,
-                    &receiver_ip,
-                    *receiver_channel_index,
-                    transmitter_name_ascii,
-                    transmitter_channel_name_ascii,
-                )?;
+        let mut found = false;
+        for (kind, name) in [
+            ("Transmitter name", transmitter_name),
+            ("Transmitter channel name", transmitter_channel_name),
+        ] {
+            let mut characters: Vec<char> = Vec::new();
+            for character in name.chars().filter(|character| !character.is_ascii()) {
+                if !characters.contains(&character) {
+                    characters.push(character);
+                }
             }
-            ControlCommands::FromFile { file_path, time } => {
-                let mut device_manager = DanteDeviceManager::new();
-
-                let file = File::open(file_path)?;
-                let lines = io::BufReader::new(file).lines();
-                for line in lines.flatten() {
-                    let (version_string, command_string) = line.split_once('|').ok_or(ParsingError::VersionDelimiter)?;
-                    let version: DanteVersion = DanteVersion::from_string(version_string).ok_or(SubscriptionError::VersionParse)?;
-
-                    if command_string.contains(':') {
-                        let (tx, rx) = command_string.split_once(':').ok_or(ParsingError::TxRxDelimiter)?;
-                        let (tx_chan, tx_device) =
-                            tx.split_once('@').ok_or(ParsingError::TxDelimiter)?;
-                        let (rx_chan, rx_ip_string) =
-                            rx.split_once('@').ok_or(ParsingError::RxDelimiter)?;
-                        let rx_chan_index: u16 = match rx_chan.parse() {
-                            Ok(chan_index) => Ok(chan_index),
-                            Err(_) => Err(ParsingError::RxChanIndexParse),
-                        }?;
-
-                        let receiver_ip = Ipv4Addr::from_str(rx_ip_string)?;
-                        let transmitter_name_ascii = tx_device.as_ascii_str()?;
-                        let transmitter_channel_name_ascii = tx_chan.as_ascii_str()?;
-
-                        device_manager.make_subscription(
-                            &version,
-                            &receiver_ip,
-                            rx_chan_index,
-                            transmitter_name_ascii,
-                            transmitter_channel_name_ascii,
-                        )?;
-                    } else {
-                        let (rx_chan, rx_ip_string) =
-                            command_string.split_once('@').ok_or(ParsingError::RxDelimiter)?;
-                        let rx_chan_index: u16 = match rx_chan.parse() {
-                            Ok(chan_index) => Ok(chan_index),
-                            Err(_) => Err(ParsingError::RxChanIndexParse),
-                        }?;
+            if characters.is_empty() {
+                continue;
+            }
+
+            let characters: Vec<String> = characters
+                .iter()
+                .map(|character| format!("{:?} (U+{:04X})", character, *character as u32))
+                .collect();
+            eprintln!(
+                "line {}: {} {:?} has non-ASCII {}: {}",
+                entry.line_number,
+                kind,
+                name,
+                characters.join(", "),
+                entry.text
+            );
+            found = true;
+        }
+        if found {
+            lines += 1;
+        }
+    }
+    lines
+}
 
-                        let receiver_ip = Ipv4Addr::from_str(rx_ip_string)?;
+/// Applies entries on `jobs` worker threads, each with its own device manager. Every line for a
+/// receiver goes to the same worker, in order, so changes to one device never race each other, and
+/// each priority band finishes before the next one starts. With a single job the lines run exactly
+/// in the order given. `pause` is slept after each line, and with `fail_fast` workers stop picking
+/// up lines after the first failure.
+fn apply_entries(
+    entries: Vec<SubscriptionEntry>,
+    jobs: usize,
+    pause: Duration,
+    fail_fast: bool,
+    rate_limiter: &RateLimiter,
+    retry: RetryPolicy,
+    op_timeout: Option<Duration>,
+    completed_operations: &AtomicUsize,
+) -> Vec<EntryResult> {
+    let stop = AtomicBool::new(false);
+    let results = Mutex::new(Vec::new());
 
-                        device_manager.clear_subscription(&version, &receiver_ip, rx_chan_index)?;
-                    }
-                    if *time > 0.0 {
-                        sleep(Duration::from_secs_f32(*time));
+    for groups in plan_work(entries, jobs) {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let workers = jobs.min(groups.len());
+        let queue = Mutex::new(groups.into_iter());
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    let mut device_manager = DanteDeviceManager::new();
+                    loop {
+                        let next = queue.lock().expect("work queue lock poisoned").next();
+                        let Some(group) = next else {
+                            break;
+                        };
+
+                        for entry in group {
+                            if stop.load(Ordering::SeqCst) {
+                                return;
+                            }
+                            log::debug!(
+                                "Applying line {} (priority {})",
+                                entry.line_number,
+                                entry.priority
+                            );
+                            let result = apply_action(
+                                &mut device_manager,
+                                &entry.action,
+                                rate_limiter,
+                                retry,
+                                op_timeout,
+                            );
+                            match result {
+                                Ok(()) => {
+                                    completed_operations.fetch_add(1, Ordering::SeqCst);
+                                }
+                                Err(_) if fail_fast => stop.store(true, Ordering::SeqCst),
+                                Err(_) => {}
+                            }
+                            results
+                                .lock()
+                                .expect("results lock poisoned")
+                                .push((entry, result));
+
+                            if !pause.is_zero() {
+                                sleep(pause);
+                            }
+                        }
                     }
-                }
+                });
             }
-            ControlCommands::Clear {
-                version,
-                receiver_ip_string,
-                receiver_channel_index,
-            } => {
-                let version = DanteVersion::from_string(version).ok_or(SubscriptionError::VersionParse)?;
+        });
+    }
 
-                let receiver_ip = Ipv4Addr::from_str(receiver_ip_string)?;
+    results.into_inner().expect("results lock poisoned")
+}
 
-                let mut device_manager = DanteDeviceManager::new();
+/// Splits entries into the work `apply_entries` hands out: one list of work items per priority
+/// band, highest first, where each item is a run of lines one worker applies in order. A single
+/// job gets everything as one item, in the order given.
+fn plan_work(entries: Vec<SubscriptionEntry>, jobs: usize) -> Vec<Vec<Vec<SubscriptionEntry>>> {
+    if jobs > 1 {
+        priority_bands(entries)
+            .into_iter()
+            .map(group_by_receiver)
+            .collect()
+    } else {
+        vec![vec![entries]]
+    }
+}
 
-                device_manager.clear_subscription(&version, &receiver_ip, *receiver_channel_index)?;
-            }
-        },
-        None => {
-            println!("No command specified. Try \"dante-cli help\"");
+/// Splits entries already sorted by priority into runs of the same priority, keeping their order.
+fn priority_bands(entries: Vec<SubscriptionEntry>) -> Vec<Vec<SubscriptionEntry>> {
+    let mut bands: Vec<Vec<SubscriptionEntry>> = Vec::new();
+    for entry in entries {
+        match bands.last_mut() {
+            Some(band) if band[0].priority == entry.priority => band.push(entry),
+            _ => bands.push(vec![entry]),
         }
     }
+    bands
+}
 
-    Ok(())
+/// Splits entries into one group per receiver, keeping the order of each receiver's lines.
+fn group_by_receiver(entries: Vec<SubscriptionEntry>) -> Vec<Vec<SubscriptionEntry>> {
+    let mut groups: Vec<(String, Vec<SubscriptionEntry>)> = Vec::new();
+    for entry in entries {
+        let receiver = match &entry.action {
+            SubscriptionLine::Make { receiver_ip, .. }
+            | SubscriptionLine::Clear { receiver_ip, .. } => receiver_ip.clone(),
+        };
+        match groups.iter_mut().find(|(ip, _)| *ip == receiver) {
+            Some((_, group)) => group.push(entry),
+            None => groups.push((receiver, vec![entry])),
+        }
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+/// Applies one subscription file action to its receiver.
+fn apply_action(
+    device_manager: &mut DanteDeviceManager,
+    action: &SubscriptionLine,
+    rate_limiter: &RateLimiter,
+    retry: RetryPolicy,
+    op_timeout: Option<Duration>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // A line that can never be sent fails once here, rather than again on every retry.
+    validate_action(action, None)?;
+    let (receiver_ip, receiver_channel_index) = action.receiver();
+    let verb = match action {
+        SubscriptionLine::Make { .. } => "Subscribing",
+        SubscriptionLine::Clear { .. } => "Clearing",
+    };
+    timed(
+        format!(
+            "{} {} channel {}",
+            verb, receiver_ip, receiver_channel_index
+        ),
+        || {
+            retry.run(|| {
+                rate_limiter.acquire();
+                send_with_timeout(device_manager, action, op_timeout)
+            })
+        },
+    )
+}
+
+/// Sends one make or clear to its receiver.
+fn send_action(
+    device_manager: &mut DanteDeviceManager,
+    action: &SubscriptionLine,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match action {
+        SubscriptionLine::Make {
+            version,
+            transmitter_name,
+            transmitter_channel_name,
+            receiver_ip,
+            receiver_channel_index,
+        } => {
+            let version = parse_version(version)?;
+            let receiver_ip = parse_ipv4(receiver_ip)?;
+            let transmitter_name_ascii = ascii_name("Transmitter name", transmitter_name)?;
+            let transmitter_channel_name_ascii =
+                ascii_name("Transmitter channel name", transmitter_channel_name)?;
+
+            device_manager
+                .make_subscription(
+                    &version,
+                    &receiver_ip,
+                    *receiver_channel_index,
+                    transmitter_name_ascii,
+                    transmitter_channel_name_ascii,
+                )
+                .map_err(control_error)?;
+        }
+        SubscriptionLine::Clear {
+            version,
+            receiver_ip,
+            receiver_channel_index,
+        } => {
+            let version = parse_version(version)?;
+            let receiver_ip = parse_ipv4(receiver_ip)?;
+
+            device_manager
+                .clear_subscription(&version, &receiver_ip, *receiver_channel_index)
+                .map_err(control_error)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sends an action, giving up on it after `--op-timeout`. Without a timeout it goes out on
+/// `device_manager` as always. With one it's sent from its own thread and device manager, since a
+/// library call that hangs can't be interrupted; a call that times out is left to finish or hang
+/// in the background while the command moves on.
+fn send_with_timeout(
+    device_manager: &mut DanteDeviceManager,
+    action: &SubscriptionLine,
+    op_timeout: Option<Duration>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let Some(timeout) = op_timeout else {
+        return send_action(device_manager, action);
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    let action = action.clone();
+    thread::spawn(move || {
+        let mut device_manager = DanteDeviceManager::new();
+        let _ = sender.send(send_action(&mut device_manager, &action));
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(_) => Err(SubscriptionError::TimedOut(timeout).into()),
+    }
+}
+
+/// Runs `f` and logs how long it took at info level, so `-vv` shows where a slow command spends
+/// its time.
+fn timed<T>(what: String, f: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = f();
+    log::info!("{} took {:.2?}", what, started.elapsed());
+    result
+}
+
+/// Catches a subscription file with nothing but blank and comment lines, which usually means the
+/// wrong path or a generator that produced nothing. With `--allow-empty` it's only a warning.
+fn check_not_empty(
+    parsed: &ParsedFile,
+    path: &str,
+    allow_empty: bool,
+    quiet: bool,
+) -> Result<(), SubscriptionError> {
+    if !parsed.entries.is_empty() {
+        return Ok(());
+    }
+    if !allow_empty {
+        return Err(SubscriptionError::EmptyFile(path.to_string()));
+    }
+    if !quiet {
+        eprintln!("No subscriptions found in {}, nothing to apply", path);
+    }
+    Ok(())
+}
+
+/// Asks on the terminal before a clear, showing what it would clear, unless `--yes` is given or
+/// stdin isn't a terminal to answer from.
+fn confirm(yes: bool, action: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if yes || !io::stdin().is_terminal() {
+        return Ok(());
+    }
+
+    eprint!("{}? [y/N] ", action);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => Err(SubscriptionError::Cancelled.into()),
+    }
+}
+
+/// How often `--watch` checks the file's modification time.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long the file has to stay unchanged before `--watch` re-reads it, so an editor that saves in
+/// several writes, or a burst of saves, is applied once.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Waits for `path` to be saved and then to settle, for `--watch`. Returns false once Ctrl-C is
+/// pressed instead. A file that's briefly missing, as when an editor replaces it, is waited out.
+fn wait_for_save(path: &str, stop: &mpsc::Receiver<()>) -> bool {
+    let modified = |path: &str| {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    let last = modified(path);
+    let mut changed_at = None;
+    let mut seen = last;
+    loop {
+        if stop.recv_timeout(WATCH_POLL_INTERVAL).is_ok() {
+            return false;
+        }
+        let now = modified(path);
+        if now != seen {
+            seen = now;
+            changed_at = Some(Instant::now());
+        }
+        if let Some(changed_at) = changed_at {
+            if seen.is_some() && seen != last && changed_at.elapsed() >= WATCH_DEBOUNCE {
+                return true;
+            }
+        }
+    }
+}
+
+/// Warns about each subscription file line that `--dedupe` dropped.
+fn report_duplicates(superseded: &[(usize, usize)]) {
+    for (line_number, replaced_by) in superseded {
+        log::warn!(
+            "line {} is superseded by line {} for the same receiver channel",
+            line_number,
+            replaced_by
+        );
+    }
+}
+
+/// Prints a status message, keeping it off stdout when stdout carries machine-readable output.
+fn status(format: OutputFormat, message: &str) {
+    match format {
+        OutputFormat::Text => println!("{}", message),
+        OutputFormat::Json | OutputFormat::Csv | OutputFormat::Yaml => eprintln!("{}", message),
+    }
+}
+
+/// Builds the `--filter` or `--exclude` matcher, reporting a bad regex the same way clap reports
+/// other invalid arguments so it fails before discovery starts.
+fn name_filter(
+    flag: &str,
+    pattern: Option<&str>,
+    regex: bool,
+    case_sensitive: bool,
+) -> Option<NameFilter> {
+    pattern.map(|pattern| {
+        NameFilter::new(pattern, regex, case_sensitive).unwrap_or_else(|error| {
+            Args::command()
+                .error(
+                    ErrorKind::ValueValidation,
+                    format!("invalid {} regex: {}", flag, error),
+                )
+                .exit()
+        })
+    })
+}
+
+/// Builds the `--name-contains` matcher, which is a plain substring match regardless of `--regex`
+/// and `--case-sensitive`.
+fn contains_filter(text: &str) -> NameFilter {
+    NameFilter::Substring {
+        needle: text.to_lowercase(),
+        case_sensitive: false,
+    }
+}
+
+/// Builds a matcher for each `--exclude` pattern.
+fn exclude_filters(patterns: &[String], regex: bool, case_sensitive: bool) -> Vec<NameFilter> {
+    patterns
+        .iter()
+        .filter_map(|pattern| name_filter("--exclude", Some(pattern), regex, case_sensitive))
+        .collect()
+}
+
+/// Takes a receiver given as an IPv4 address as is, and looks one given by device name up through
+/// discovery.
+fn resolve_receiver(
+    receiver: &str,
+    discovery: &mut SharedDiscovery,
+    fuzzy: bool,
+) -> Result<Ipv4Addr, Box<dyn std::error::Error>> {
+    if let Ok(ip) = Ipv4Addr::from_str(receiver) {
+        return Ok(ip);
+    }
+    reject_ipv6(receiver)?;
+
+    timed(format!("Resolving {}", receiver), || {
+        discovery.find(|devices| ip_for_name(devices, receiver, fuzzy))
+    })
+}
+
+/// Waits for every transmitter to turn up in discovery, for `--verify-transmitter`, and returns
+/// the names that still hadn't when the discovery time ran out.
+fn missing_transmitters(
+    names: &[String],
+    discovery: &mut SharedDiscovery,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut seen = Vec::new();
+    let result = timed(
+        format!("Looking for {} transmitter(s)", names.len()),
+        || {
+            discovery.find(|devices| {
+                seen = devices.iter().map(|device| device.name.clone()).collect();
+                find_transmitters(devices, names)
+            })
+        },
+    );
+
+    match result {
+        Ok(()) => Ok(Vec::new()),
+        Err(error) if error.is::<ResolveError>() => Ok(names
+            .iter()
+            .filter(|name| !seen.contains(name))
+            .cloned()
+            .collect()),
+        Err(error) => Err(error),
+    }
+}
+
+/// The `--cache` control commands resolve from, if one was given.
+fn discovery_cache(args: &Args) -> Option<DiscoveryCache> {
+    args.cache
+        .as_deref()
+        .map(|path| DiscoveryCache::load(path, args.cache_ttl))
+}
+
+/// How long until the wall clock next reaches a multiple of `interval` since the Unix epoch, for
+/// `monitor --align`.
+fn until_aligned(interval: Duration) -> Duration {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let interval = interval.as_nanos().max(1);
+    Duration::from_nanos((interval - since_epoch.as_nanos() % interval) as u64)
+}
+
+/// Reads the names for `resolve --stdin`, one per line, skipping blank lines.
+fn read_names(input: impl BufRead) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        let name = line.trim();
+        if !name.is_empty() {
+            names.push(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+/// Looks every name up against one discovery, waiting until all of them resolve or `time` runs
+/// out. Names that still don't resolve then have no address, and why is logged as a warning.
+fn resolve_names(
+    names: &[String],
+    time: Duration,
+    fuzzy: bool,
+    report_timing: bool,
+) -> Result<Vec<(String, Option<Ipv4Addr>)>, Box<dyn std::error::Error>> {
+    let mut seen = Vec::new();
+    let mut timing = DiscoveryTiming::start();
+    let result = discover_until(time, &mut timing, |devices| {
+        seen = devices.to_vec();
+        names
+            .iter()
+            .try_for_each(|name| ip_for_name(devices, name, fuzzy).map(|_| ()))
+    });
+    if report_timing {
+        timing.report();
+    }
+    match result {
+        Ok(()) => {}
+        Err(error) if error.is::<ResolveError>() => {}
+        Err(error) => return Err(error),
+    }
+
+    Ok(names
+        .iter()
+        .map(|name| match ip_for_name(&seen, name, fuzzy) {
+            Ok(ip) => (name.clone(), Some(ip)),
+            Err(error) => {
+                log::warn!("{}", error);
+                (name.clone(), None)
+            }
+        })
+        .collect())
+}
+
+/// Looks every address up against one discovery, like `resolve_names`. An address no device
+/// answers from has no names.
+fn resolve_ips(
+    ips: &[Ipv4Addr],
+    time: Duration,
+    report_timing: bool,
+) -> Result<Vec<(Ipv4Addr, Vec<String>)>, Box<dyn std::error::Error>> {
+    let mut seen = Vec::new();
+    let mut timing = DiscoveryTiming::start();
+    let result = discover_until(time, &mut timing, |devices| {
+        seen = devices.to_vec();
+        ips.iter()
+            .try_for_each(|ip| names_for_ip(devices, *ip).map(|_| ()))
+    });
+    if report_timing {
+        timing.report();
+    }
+    match result {
+        Ok(()) => {}
+        Err(error) if error.is::<ResolveError>() => {}
+        Err(error) => return Err(error),
+    }
+
+    Ok(ips
+        .iter()
+        .map(|ip| (*ip, names_for_ip(&seen, *ip).unwrap_or_default()))
+        .collect())
+}
+
+/// Checks that each receiver answers discovery and prints the result.
+fn probe(
+    receivers: &[Ipv4Addr],
+    discovery: &mut SharedDiscovery,
+    report_timing: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let reachable = probe_receivers(receivers, discovery);
+    if report_timing {
+        discovery.report_timing();
+    }
+
+    for (ip, name) in reachable? {
+        println!("{} ({}): reachable", ip, name);
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = match parse_with_config::<Args>() {
+        Ok(args) => args,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            return ExitCode::from(2);
+        }
+    };
+
+    // --quiet leaves nothing to print, but a script still needs to know that nothing ran.
+    if args.quiet && args.command.is_none() {
+        return ExitCode::from(2);
+    }
+
+    let json_errors = writes_json(&args.command);
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            if json_errors {
+                let report = serde_json::json!({
+                    "error": error.to_string(),
+                    "kind": error_kind(error.as_ref()),
+                });
+                eprintln!("{}", report);
+            } else {
+                eprintln!("Error: {}", error);
+            }
+            ExitCode::from(exit_code(error.as_ref()))
+        }
+    }
+}
+
+/// Whether the command prints JSON on stdout, in which case a failure is reported on stderr as a
+/// JSON object too, so a pipeline can parse both the same way.
+fn writes_json(command: &Option<Commands>) -> bool {
+    matches!(
+        command,
+        Some(Commands::ListDevices {
+            format: OutputFormat::Json,
+            ..
+        }) | Some(Commands::Monitor {
+            format: MonitorFormat::Ndjson,
+            ..
+        }) | Some(Commands::Doctor {
+            format: DoctorFormat::Json,
+            ..
+        })
+    )
+}
+
+/// A stable name for what went wrong, for the `kind` of a JSON error object.
+fn error_kind(error: &(dyn std::error::Error + 'static)) -> &'static str {
+    if let Some(error) = error.downcast_ref::<DiscoveryError>() {
+        return match error {
+            DiscoveryError::Start => "discovery_start",
+            DiscoveryError::PermissionDenied => "discovery_permission_denied",
+            DiscoveryError::AddrInUse => "discovery_port_in_use",
+        };
+    }
+    if let Some(error) = error.downcast_ref::<ListingError>() {
+        return match error {
+            ListingError::Empty => "empty",
+            ListingError::TooFewDevices { .. } => "too_few_devices",
+        };
+    }
+    if error.is::<ResolveError>() {
+        return "resolve";
+    }
+    if error.is::<DoctorError>() {
+        return "checks_failed";
+    }
+    if error.is::<ProbeError>() {
+        return "unreachable";
+    }
+    if error.is::<SnapshotError>() {
+        return "snapshot";
+    }
+    if error.is::<OutputError>() || error.is::<io::Error>() {
+        return "io";
+    }
+    if error.is::<AddrParseError>() || error.is::<AddressError>() {
+        return "invalid_address";
+    }
+    "other"
+}
+
+/// Maps an error to the exit status documented in `EXIT_CODES_HELP`.
+fn exit_code(error: &(dyn std::error::Error + 'static)) -> u8 {
+    if let Some(error) = error.downcast_ref::<SubscriptionError>() {
+        return match error {
+            SubscriptionError::VersionParse(_)
+            | SubscriptionError::MissingVersion
+            | SubscriptionError::ChannelOutOfRange { .. }
+            | SubscriptionError::InvalidLines(_)
+            | SubscriptionError::EmptyFile(_) => 2,
+            SubscriptionError::FailedLines(_)
+            | SubscriptionError::FailedReceivers(_)
+            | SubscriptionError::Control(_)
+            | SubscriptionError::TimedOut(_) => 4,
+            SubscriptionError::Cancelled => 1,
+        };
+    }
+    if error.is::<LineError>()
+        || error.is::<AddrParseError>()
+        || error.is::<AddressError>()
+        || error.is::<NonAsciiName>()
+    {
+        return 2;
+    }
+    match error.downcast_ref::<FileError>() {
+        Some(FileError::Json(_)) => return 2,
+        Some(FileError::Io(_)) => return 5,
+        None => {}
+    }
+    if let Some(DiscoveryError::PermissionDenied | DiscoveryError::AddrInUse) =
+        error.downcast_ref::<DiscoveryError>()
+    {
+        return 6;
+    }
+    if error.is::<DiscoveryError>()
+        || error.is::<ResolveError>()
+        || error.is::<ProbeError>()
+        || error.is::<ListingError>()
+    {
+        return 3;
+    }
+    if error.is::<io::Error>() || error.is::<OutputError>() || error.is::<SnapshotError>() {
+        return 5;
+    }
+    1
+}
+
+fn run(args: Args) -> Result<(), Box<dyn std::error::Error>> {
+    logging::init(
+        args.quiet,
+        args.verbose as usize,
+        args.log_format,
+        args.log_file.as_deref(),
+        args.log_append,
+    )?;
+    set_discovery_retries(args.discovery_retries);
+
+    let color = if args.no_color {
+        ColorChoice::Never
+    } else {
+        args.color
+    };
+
+    let sort_order = if args.preserve_order {
+        SortOrder::None
+    } else {
+        args.sort
+    };
+
+    let rate_limiter = RateLimiter::new(args.limit_rate);
+
+    let completed_operations = Arc::new(AtomicUsize::new(0));
+    if let Some(deadline) = args.deadline {
+        spawn_watchdog(deadline, completed_operations.clone());
+    }
+
+    match &args.command {
+        Some(Commands::ListDevices {
+            time,
+            detailed,
+            format,
+            since,
+            change_fields,
+            filter,
+            name_contains,
+            exclude,
+            regex,
+            case_sensitive,
+            fail_if_empty,
+            require_devices,
+            max_devices,
+            min_devices,
+            wait_for,
+            retry_discovery_until,
+            output,
+            append,
+            repeat,
+            until_found,
+            table,
+            oneline,
+            group_by,
+            fields,
+        }) => {
+            if group_by.is_some() && *format != OutputFormat::Text {
+                Args::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "--group-by only applies to text output",
+                    )
+                    .exit()
+            }
+            if *oneline && *format != OutputFormat::Text {
+                Args::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "--oneline only applies to text output",
+                    )
+                    .exit()
+            }
+            let filter = name_filter("--filter", filter.as_deref(), *regex, *case_sensitive)
+                .or_else(|| name_contains.as_deref().map(contains_filter));
+            let excludes = exclude_filters(exclude, *regex, *case_sensitive);
+            let snapshot = since.as_deref().map(load_snapshot).transpose()?;
+            let mut out = open_output(output.as_deref(), *append)?;
+            let max_devices = max_devices.map(|max| max as usize);
+
+            let mut timing = DiscoveryTiming::start();
+            let mut pass = 0;
+            let mut union = Vec::new();
+            let mut devices = loop {
+                let device_manager = DanteDeviceManager::new();
+                start_discovery(&device_manager)?;
+
+                if !args.quiet {
+                    status(*format, "Discovering Devices...");
+                }
+
+                wait_for_devices(
+                    &device_manager,
+                    *time,
+                    &mut timing,
+                    show_progress(args.quiet),
+                    until_found.as_deref(),
+                    max_devices,
+                );
+
+                device_manager.stop_discovery();
+
+                let devices = collect_devices(&device_manager);
+                if *repeat > 1 {
+                    pass += 1;
+                    let added = merge_devices(&mut union, devices);
+                    log::info!(
+                        "Discovery pass {}/{} found {} new device(s)",
+                        pass,
+                        repeat,
+                        added
+                    );
+                    if pass < *repeat {
+                        continue;
+                    }
+                    break union;
+                }
+                if has_expected_devices(&devices, *min_devices, wait_for) {
+                    break devices;
+                }
+                if pass >= *retry_discovery_until {
+                    if *retry_discovery_until > 0 {
+                        log::warn!(
+                            "Expected devices still missing after {} discovery restart(s)",
+                            pass
+                        );
+                    }
+                    break devices;
+                }
+
+                pass += 1;
+                log::warn!(
+                    "Found {} device(s) without the expected set, restarting discovery ({}/{})",
+                    devices.len(),
+                    pass,
+                    retry_discovery_until
+                );
+            };
+
+            if args.timing {
+                timing.report();
+            }
+
+            let truncated = truncate_devices(&mut devices, max_devices);
+            check_device_count(devices.len(), *require_devices)?;
+            if let Some(name) = until_found {
+                devices.retain(|device| &device.name == name);
+                if devices.is_empty() {
+                    return Err(ResolveError::NoDeviceNamed(name.clone()).into());
+                }
+            }
+
+            sort_devices(&mut devices, sort_order);
+            if let Some(filter) = &filter {
+                retain_matching(&mut devices, filter);
+            }
+            remove_matching(&mut devices, &excludes);
+
+            let printed = match snapshot {
+                Some(snapshot) => {
+                    if !args.quiet {
+                        status(*format, "Changed Devices:\n");
+                    }
+                    let changes = diff_devices(&snapshot, &devices, change_fields);
+                    print_device_changes(&mut out, &changes, *format)?;
+                    changes.len()
+                }
+                None => {
+                    if !args.quiet {
+                        status(*format, "Devices Found:\n");
+                    }
+                    let table = table.is_some_and(|table| table.enabled(output.is_some()));
+                    let groups = match group_by {
+                        Some(by) => group_devices(&devices, *by),
+                        None => vec![(String::new(), devices.clone())],
+                    };
+                    for (key, group) in &groups {
+                        if group_by.is_some() {
+                            writeln!(
+                                out,
+                                "========== {}: {} device(s) ==========",
+                                key,
+                                group.len()
+                            )?;
+                        }
+                        if *oneline {
+                            print_device_lines(&mut out, group, fields.as_deref())?;
+                        } else if *detailed && table && *format == OutputFormat::Text {
+                            print_device_table(&mut out, group, fields.as_deref())?;
+                        } else {
+                            print_devices(
+                                &mut out,
+                                group,
+                                *detailed,
+                                *format,
+                                color.enabled(output.is_some()),
+                                fields.as_deref(),
+                            )?;
+                        }
+                    }
+                    devices.len()
+                }
+            };
+            out.finish()?;
+
+            if !args.quiet && *format == OutputFormat::Text {
+                if since.is_some() {
+                    eprintln!("{} changed device(s)", printed);
+                } else {
+                    eprintln!("{} device(s)", printed);
+                }
+            }
+            if truncated && !args.quiet {
+                eprintln!(
+                    "Truncated at {} device(s), more were discovered",
+                    max_devices.unwrap_or_default()
+                );
+            }
+
+            if *fail_if_empty && printed == 0 {
+                return Err(ListingError::Empty.into());
+            }
+        }
+        Some(Commands::Monitor {
+            print_interval,
+            align,
+            detailed,
+            oneline,
+            fields,
+            history,
+            history_format,
+            filter,
+            name_contains,
+            exclude,
+            regex,
+            case_sensitive,
+            output,
+            truncate,
+            output_dir,
+            keep,
+            count,
+            require_devices,
+            max_devices,
+            format,
+            changes_only,
+            change_fields,
+            on_change,
+            webhook,
+            debounce,
+            poll_interval,
+        }) => {
+            if *oneline && *format != MonitorFormat::Text {
+                Args::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "--oneline only applies to text output",
+                    )
+                    .exit()
+            }
+            let filter = name_filter("--filter", filter.as_deref(), *regex, *case_sensitive)
+                .or_else(|| name_contains.as_deref().map(contains_filter));
+            let excludes = exclude_filters(exclude, *regex, *case_sensitive);
+            let mut out = open_output(output.as_deref(), !*truncate)?;
+            let interval_files = output_dir
+                .as_deref()
+                .map(|dir| {
+                    let extension = match format {
+                        MonitorFormat::Text => "txt",
+                        MonitorFormat::Ndjson => "json",
+                    };
+                    IntervalFiles::open(dir, extension, keep.map(|keep| keep as usize))
+                })
+                .transpose()?;
+            let to_file = output.is_some() || interval_files.is_some();
+            let mut history = history
+                .as_deref()
+                .map(|path| {
+                    HistoryWriter::open(path, *history_format, sort_order == SortOrder::None)
+                })
+                .transpose()?;
+            let mut hook = ChangeHook::new(on_change.clone(), webhook.clone(), *debounce);
+
+            let device_manager = DanteDeviceManager::new();
+            start_discovery(&device_manager)?;
+
+            let (stop_sender, stop_receiver) = mpsc::channel();
+            ctrlc::set_handler(move || {
+                let _ = stop_sender.send(());
+            })?;
+
+            if !args.quiet {
+                match format {
+                    MonitorFormat::Text => println!("Starting monitoring"),
+                    MonitorFormat::Ndjson => eprintln!("Starting monitoring"),
+                }
+            }
+
+            // Sleep until the next tick rather than for a whole interval, so time spent discovering
+            // and printing doesn't make the cadence drift.
+            let interval = *print_interval;
+            if *align && interval.is_zero() {
+                Args::command()
+                    .error(
+                        ErrorKind::InvalidValue,
+                        "--align needs a --print-interval above zero",
+                    )
+                    .exit()
+            }
+            let poll_interval = poll_interval.unwrap_or(interval);
+            let max_devices = max_devices.map(|max| max as usize);
+            let narrow = |devices: &mut Vec<DeviceInfo>| {
+                let truncated = truncate_devices(devices, max_devices);
+                sort_devices(devices, sort_order);
+                if let Some(filter) = &filter {
+                    retain_matching(devices, filter);
+                }
+                remove_matching(devices, &excludes);
+                truncated
+            };
+            let mut next_tick = Instant::now()
+                + if *align {
+                    until_aligned(interval)
+                } else {
+                    interval
+                };
+            let mut previous = Vec::new();
+            let mut polled_changes = Vec::new();
+            let mut printed = 0;
+            let mut progress = Progress::new(show_progress(args.quiet));
+            'monitor: while *count == 0 || printed < *count {
+                if next_tick <= Instant::now() {
+                    log::info!(
+                        "Printing took longer than the {:?} interval, not waiting",
+                        interval
+                    );
+                }
+                // Between prints, poll every --poll-interval so changes are caught as they happen
+                // rather than only on the print cadence. The first interval is the baseline
+                // everything else is compared against, so it isn't polled and doesn't count as a
+                // change for the hook; it only wakes up to redraw the progress line.
+                loop {
+                    let wait = next_tick.saturating_duration_since(Instant::now());
+                    let poll = if printed > 0 {
+                        wait.min(poll_interval)
+                    } else if progress.enabled() {
+                        wait.min(WAIT_POLL_INTERVAL)
+                    } else {
+                        wait
+                    };
+                    if stop_receiver.recv_timeout(poll).is_ok() {
+                        progress.clear();
+                        break 'monitor;
+                    }
+                    if poll >= wait {
+                        break;
+                    }
+                    if printed == 0 {
+                        progress.update(
+                            next_tick.saturating_duration_since(Instant::now()),
+                            Some(device_manager.get_device_names().len()),
+                        );
+                        continue;
+                    }
+
+                    let mut devices = collect_devices(&device_manager);
+                    narrow(&mut devices);
+                    let changes = diff_intervals(&previous, &devices, change_fields);
+                    if let Some(hook) = &mut hook {
+                        hook.observe(&changes);
+                    }
+                    polled_changes.extend(changes);
+                    previous = devices;
+                }
+                progress.clear();
+
+                let mut devices = collect_devices(&device_manager);
+                check_device_count(devices.len(), *require_devices)?;
+                let truncated = narrow(&mut devices);
+
+                let tick_changes = diff_intervals(&previous, &devices, change_fields);
+                if let (Some(hook), true) = (&mut hook, printed > 0) {
+                    hook.observe(&tick_changes);
+                }
+                let mut changes = std::mem::take(&mut polled_changes);
+                changes.extend(tick_changes);
+                let changes = changes_only.then_some(changes);
+                let unchanged = changes.as_ref().is_some_and(Vec::is_empty);
+
+                let sampled_at = Utc::now();
+                let timestamp = sampled_at.to_rfc3339_opts(SecondsFormat::Millis, true);
+                if let (Some(files), false) = (&interval_files, unchanged) {
+                    out = files.create(sampled_at)?;
+                }
+                match format {
+                    _ if unchanged => {}
+                    MonitorFormat::Text => {
+                        if to_file {
+                            writeln!(out, "========== {} ==========", timestamp)?;
+                        } else {
+                            writeln!(out, "=================================")?;
+                        }
+                        match &changes {
+                            Some(changes) => {
+                                print_device_changes(&mut out, changes, OutputFormat::Text)?
+                            }
+                            None if *oneline => {
+                                print_device_lines(&mut out, &devices, fields.as_deref())?
+                            }
+                            None => print_devices(
+                                &mut out,
+                                &devices,
+                                *detailed,
+                                OutputFormat::Text,
+                                color.enabled(to_file),
+                                fields.as_deref(),
+                            )?,
+                        }
+                    }
+                    MonitorFormat::Ndjson => {
+                        if !args.quiet {
+                            eprintln!("=================================");
+                        }
+                        match &changes {
+                            Some(changes) => print_change_sample(&mut out, &timestamp, changes)?,
+                            None => print_device_sample(
+                                &mut out,
+                                &timestamp,
+                                &devices,
+                                *detailed,
+                                fields.as_deref(),
+                            )?,
+                        }
+                    }
+                }
+                out.finish()?;
+                if let (Some(files), false) = (&interval_files, unchanged) {
+                    files.prune()?;
+                }
+                if !args.quiet && !unchanged && *format == MonitorFormat::Text {
+                    eprintln!("{} device(s)", devices.len());
+                }
+                if truncated && !args.quiet {
+                    eprintln!(
+                        "Truncated at {} device(s), more were discovered",
+                        max_devices.unwrap_or_default()
+                    );
+                }
+
+                if let Some(history) = &mut history {
+                    let names: Vec<String> =
+                        devices.iter().map(|device| device.name.clone()).collect();
+                    history.record(&names)?;
+                }
+                previous = devices;
+                next_tick += interval;
+                if *align {
+                    // Skip boundaries that have already gone by rather than printing late, so every
+                    // print stays on one.
+                    while next_tick <= Instant::now() {
+                        next_tick += interval;
+                    }
+                } else {
+                    next_tick = next_tick.max(Instant::now());
+                }
+                printed += 1;
+            }
+
+            device_manager.stop_discovery();
+            if !args.quiet {
+                eprintln!("Stopping monitor");
+            }
+        }
+        Some(Commands::Debug(debug_command)) => {
+            let (services, time, output) = match debug_command {
+                DebugCommands::PrintCMC { time, output } => (&DEBUG_SERVICES[0..1], time, output),
+                DebugCommands::PrintDBC { time, output } => (&DEBUG_SERVICES[1..2], time, output),
+                DebugCommands::PrintARC { time, output } => (&DEBUG_SERVICES[2..3], time, output),
+                DebugCommands::PrintCHAN { time, output } => (&DEBUG_SERVICES[3..4], time, output),
+                DebugCommands::PrintAll { time, output } => (&DEBUG_SERVICES[..], time, output),
+                DebugCommands::ListInterfaces => return Ok(print_interfaces()?),
+            };
+
+            let time = *time;
+            let mut capture = output
+                .as_deref()
+                .map(|path| open_output(Some(path), false))
+                .transpose()?;
+
+            // The print functions write straight to stdout, so running them at the same time
+            // would interleave their output. They run one after another instead. Their discovery
+            // happens inside the library, so the progress line can only count the time down.
+            let progress = show_progress(args.quiet);
+            for (service, print) in services {
+                if services.len() > 1 {
+                    println!("========== {} ==========", service);
+                }
+                match &mut capture {
+                    None => with_countdown(progress, time, || print(time)),
+                    Some(file) => {
+                        let printed =
+                            capture_stdout(|| with_countdown(progress, time, || print(time)))?;
+                        print!("{}", printed);
+
+                        writeln!(
+                            file,
+                            "========== {} {} ({:.2}s) ==========",
+                            Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+                            service,
+                            time.as_secs_f32()
+                        )?;
+                        file.write_all(printed.as_bytes())?;
+                        file.finish()?;
+                    }
+                }
+            }
+        }
+        Some(Commands::Control(control_command)) => match control_command {
+            ControlCommands::Make {
+                version: first,
+                transmitter_name,
+                transmitter_channel_name,
+                receiver_ip_string,
+                receiver_channel_index,
+                discovery_time,
+                probe_only,
+                verify_transmitter,
+                no_discovery,
+                retry,
+                retry_delay,
+            } => {
+                let version = &command_version(
+                    first,
+                    receiver_channel_index.is_empty(),
+                    "RECEIVER_CHANNEL_INDEX",
+                    args.default_version.as_deref(),
+                )?;
+                parse_version(version)?;
+                // Without the version, every positional landed one place early.
+                let shifted_receivers: Vec<String>;
+                let shifted_indices: Vec<u16>;
+                let (
+                    transmitter_name,
+                    transmitter_channel_name,
+                    receiver_ip_string,
+                    receiver_channel_index,
+                ) = if receiver_channel_index.is_empty() {
+                    shifted_receivers = transmitter_channel_name
+                        .split(',')
+                        .map(str::to_string)
+                        .collect();
+                    shifted_indices = receiver_ip_string
+                        .iter()
+                        .map(String::as_str)
+                        .map(channel_index_arg)
+                        .collect();
+                    (
+                        first,
+                        transmitter_name,
+                        &shifted_receivers,
+                        &shifted_indices,
+                    )
+                } else {
+                    (
+                        transmitter_name,
+                        transmitter_channel_name,
+                        receiver_ip_string,
+                        receiver_channel_index,
+                    )
+                };
+                if *no_discovery {
+                    for receiver in receiver_ip_string {
+                        reject_ipv6(receiver)?;
+                    }
+                    let lookup = Some(transmitter_name)
+                        .filter(|name| Ipv4Addr::from_str(name).is_ok())
+                        .map(|ip| {
+                            format!(
+                                "the transmitter {} would have to be looked up by address",
+                                ip
+                            )
+                        })
+                        .or_else(|| {
+                            receiver_ip_string
+                                .iter()
+                                .find(|receiver| Ipv4Addr::from_str(receiver).is_err())
+                                .map(|name| {
+                                    format!(
+                                        "the receiver {} would have to be resolved by name",
+                                        name
+                                    )
+                                })
+                        });
+                    if let Some(lookup) = lookup {
+                        Args::command()
+                            .error(
+                                ErrorKind::ArgumentConflict,
+                                format!("--no-discovery can't be used here, {}", lookup),
+                            )
+                            .exit()
+                    }
+                }
+                if receiver_channel_index.len() != 1
+                    && receiver_channel_index.len() != receiver_ip_string.len()
+                {
+                    Args::command()
+                        .error(
+                            ErrorKind::WrongNumberOfValues,
+                            format!(
+                                "{} receivers but {} receiver channel indices were given, give a single index for all of them or one per receiver",
+                                receiver_ip_string.len(),
+                                receiver_channel_index.len()
+                            ),
+                        )
+                        .exit()
+                }
+                for index in receiver_channel_index {
+                    check_channel(*index, args.max_channels)?;
+                }
+
+                let mut discovery = SharedDiscovery::new(*discovery_time, discovery_cache(&args));
+                let transmitter_name = match Ipv4Addr::from_str(transmitter_name) {
+                    Ok(transmitter_ip) => timed(format!("Resolving {}", transmitter_ip), || {
+                        discovery.find(|devices| name_for_ip(devices, transmitter_ip))
+                    })?,
+                    Err(_) if args.fuzzy && !*no_discovery => {
+                        reject_ipv6(transmitter_name)?;
+                        timed(format!("Resolving {}", transmitter_name), || {
+                            discovery.find(|devices| {
+                                device_named(devices, transmitter_name, true)
+                                    .map(|device| device.name.clone())
+                            })
+                        })?
+                    }
+                    Err(_) => {
+                        reject_ipv6(transmitter_name)?;
+                        // Only a name used as given needs checking: the arms above already found
+                        // their transmitter through discovery.
+                        if *verify_transmitter {
+                            let missing = missing_transmitters(
+                                slice::from_ref(transmitter_name),
+                                &mut discovery,
+                            )?;
+                            if let Some(name) = missing.into_iter().next() {
+                                return Err(ResolveError::TransmitterNotFound(name).into());
+                            }
+                        }
+                        transmitter_name.clone()
+                    }
+                };
+
+                let mut receivers = Vec::new();
+                for (receiver, index) in receiver_ip_string
+                    .iter()
+                    .zip(receiver_channel_index.iter().cycle())
+                {
+                    let receiver_ip = resolve_receiver(receiver, &mut discovery, args.fuzzy)?;
+                    receivers.push((receiver_ip, *index));
+                }
+                if *probe_only {
+                    let ips: Vec<Ipv4Addr> = receivers.iter().map(|(ip, _)| *ip).collect();
+                    return probe(&ips, &mut discovery, args.timing);
+                }
+                if args.timing {
+                    discovery.report_timing();
+                }
+
+                ascii_name("Transmitter name", &transmitter_name)?;
+                ascii_name("Transmitter channel name", transmitter_channel_name)?;
+
+                let device_manager = discovery.device_manager();
+                let retry = RetryPolicy::new(*retry, *retry_delay);
+
+                let mut failed = 0;
+                for (receiver_ip, receiver_channel_index) in &receivers {
+                    let action = SubscriptionLine::Make {
+                        version: version.clone(),
+                        transmitter_name: transmitter_name.clone(),
+                        transmitter_channel_name: transmitter_channel_name.clone(),
+                        receiver_ip: receiver_ip.to_string(),
+                        receiver_channel_index: *receiver_channel_index,
+                    };
+                    let result = apply_action(
+                        device_manager,
+                        &action,
+                        &rate_limiter,
+                        retry,
+                        args.op_timeout,
+                    );
+                    match result {
+                        Ok(_) => {
+                            completed_operations.fetch_add(1, Ordering::SeqCst);
+                        }
+                        // A single receiver fails exactly as it always has.
+                        Err(error) if receivers.len() == 1 => return Err(error),
+                        Err(error) => {
+                            eprintln!(
+                                "{} channel {}: {}",
+                                receiver_ip, receiver_channel_index, error
+                            );
+                            failed += 1;
+                        }
+                    }
+                }
+
+                if receivers.len() > 1 {
+                    if !args.quiet {
+                        println!(
+                            "{} receiver(s) succeeded, {} failed",
+                            receivers.len() - failed,
+                            failed
+                        );
+                    }
+                    if failed > 0 {
+                        return Err(SubscriptionError::FailedReceivers(failed).into());
+                    }
+                }
+            }
+            ControlCommands::FromFile {
+                file_path,
+                time,
+                discovery_time,
+                verify_transmitter,
+                probe_only,
+                dry_run,
+                fail_fast,
+                retry,
+                retry_delay,
+                jobs,
+                dedupe,
+                input_format,
+                summary_json,
+                allow_empty,
+                expand_env,
+                watch,
+                check_encoding,
+                force,
+            } => {
+                let input_format = input_format.unwrap_or_else(|| InputFormat::for_path(file_path));
+                if *watch && file_path == "-" {
+                    Args::command()
+                        .error(
+                            ErrorKind::ArgumentConflict,
+                            "--watch needs a file path, stdin can't be watched",
+                        )
+                        .exit()
+                }
+
+                if *dry_run {
+                    let parsed = parse_subscription_file(
+                        file_path,
+                        args.default_version.as_deref(),
+                        input_format,
+                        *expand_env,
+                    )?;
+                    check_not_empty(&parsed, file_path, *allow_empty, args.quiet)?;
+
+                    let mut entries = Vec::new();
+                    let mut invalid = 0;
+                    for entry in parsed.entries {
+                        match entry {
+                            Ok(entry) => match validate_action(&entry.action, args.max_channels) {
+                                Ok(()) => entries.push(entry),
+                                Err(error) => {
+                                    eprintln!(
+                                        "line {}: {}: {}",
+                                        entry.line_number, error, entry.text
+                                    );
+                                    invalid += 1;
+                                }
+                            },
+                            Err(error) => {
+                                eprintln!("{} ({:?})", error, error.error);
+                                invalid += 1;
+                            }
+                        }
+                    }
+                    if invalid > 0 {
+                        return Err(SubscriptionError::InvalidLines(invalid).into());
+                    }
+                    if *dedupe {
+                        report_duplicates(&dedupe_entries(&mut entries));
+                    }
+
+                    entries.sort_by_key(|entry| Reverse(entry.priority));
+                    for entry in entries {
+                        println!("line {}: would {}", entry.line_number, entry.action);
+                    }
+                    return Ok(());
+                }
+
+                if *probe_only {
+                    let mut receivers = Vec::new();
+                    for entry in read_subscription_file(
+                        file_path,
+                        args.default_version.as_deref(),
+                        input_format,
+                        *expand_env,
+                    )? {
+                        let receiver_ip = match &entry.action {
+                            SubscriptionLine::Make { receiver_ip, .. }
+                            | SubscriptionLine::Clear { receiver_ip, .. } => {
+                                parse_ipv4(receiver_ip)?
+                            }
+                        };
+                        if !receivers.contains(&receiver_ip) {
+                            receivers.push(receiver_ip);
+                        }
+                    }
+                    let mut discovery =
+                        SharedDiscovery::new(*discovery_time, discovery_cache(&args));
+                    return probe(&receivers, &mut discovery, args.timing);
+                }
+
+                let parse_started = Instant::now();
+                let parsed = parse_subscription_file(
+                    file_path,
+                    args.default_version.as_deref(),
+                    input_format,
+                    *expand_env,
+                )?;
+                check_not_empty(&parsed, file_path, *allow_empty, args.quiet)?;
+                let (mut entries, mut failures, mut stats) =
+                    split_parsed(parsed, *fail_fast, args.max_channels)?;
+                let parse_time = parse_started.elapsed();
+
+                if *check_encoding {
+                    let non_ascii = report_non_ascii_names(&entries);
+                    if non_ascii > 0 && !*force {
+                        return Err(SubscriptionError::InvalidLines(non_ascii).into());
+                    }
+                    if non_ascii > 0 {
+                        log::warn!(
+                            "Applying anyway because of --force, {} line(s) with non-ASCII names will fail",
+                            non_ascii
+                        );
+                    }
+                }
+
+                stats.duplicates = dedupe.then(|| {
+                    let superseded = dedupe_entries(&mut entries);
+                    report_duplicates(&superseded);
+                    superseded.len()
+                });
+
+                if *verify_transmitter {
+                    let mut names: Vec<String> = Vec::new();
+                    for entry in &entries {
+                        if let SubscriptionLine::Make {
+                            transmitter_name, ..
+                        } = &entry.action
+                        {
+                            if !names.contains(transmitter_name) {
+                                names.push(transmitter_name.clone());
+                            }
+                        }
+                    }
+
+                    let mut discovery =
+                        SharedDiscovery::new(*discovery_time, discovery_cache(&args));
+                    let missing = missing_transmitters(&names, &mut discovery)?;
+                    if args.timing {
+                        discovery.report_timing();
+                    }
+                    let (verified, unverified): (Vec<_>, Vec<_>) =
+                        entries.into_iter().partition(|entry| match &entry.action {
+                            SubscriptionLine::Make {
+                                transmitter_name, ..
+                            } => !missing.contains(transmitter_name),
+                            SubscriptionLine::Clear { .. } => true,
+                        });
+                    entries = verified;
+                    for entry in unverified {
+                        let SubscriptionLine::Make {
+                            transmitter_name, ..
+                        } = &entry.action
+                        else {
+                            continue;
+                        };
+                        let error = ResolveError::TransmitterNotFound(transmitter_name.clone());
+                        if *fail_fast {
+                            return Err(error.into());
+                        }
+                        failures.push((entry.line_number, entry.text, error.into()));
+                    }
+                }
+
+                // The sort is stable, so lines of equal priority keep their file order.
+                entries.sort_by_key(|entry| Reverse(entry.priority));
+
+                let operations = entries.len();
+                let mut applied: Vec<SubscriptionLine> =
+                    entries.iter().map(|entry| entry.action.clone()).collect();
+                let apply = |entries| {
+                    apply_entries(
+                        entries,
+                        *jobs as usize,
+                        *time,
+                        *fail_fast,
+                        &rate_limiter,
+                        RetryPolicy::new(*retry, *retry_delay),
+                        args.op_timeout,
+                        &completed_operations,
+                    )
+                };
+                let apply_started = Instant::now();
+                let results = apply(entries);
+                log::info!(
+                    "Timing: reading the file took {:.2?}, applying {} line(s) took {:.2?}, {:.2?} in total",
+                    parse_time,
+                    operations,
+                    apply_started.elapsed(),
+                    parse_started.elapsed()
+                );
+                let report = report_file_results(
+                    results,
+                    failures,
+                    &stats,
+                    *fail_fast,
+                    args.quiet,
+                    summary_json.as_deref(),
+                );
+                if !*watch {
+                    return report;
+                }
+                if let Err(error) = report {
+                    eprintln!("Error: {}", error);
+                }
+
+                let (stop_sender, stop_receiver) = mpsc::channel();
+                ctrlc::set_handler(move || {
+                    let _ = stop_sender.send(());
+                })?;
+                if !args.quiet {
+                    println!("Watching {} for changes", file_path);
+                }
+                while wait_for_save(file_path, &stop_receiver) {
+                    let parsed = match parse_subscription_file(
+                        file_path,
+                        args.default_version.as_deref(),
+                        input_format,
+                        *expand_env,
+                    ) {
+                        Ok(parsed) => parsed,
+                        Err(error) => {
+                            eprintln!("Error: {}", error);
+                            continue;
+                        }
+                    };
+                    let (mut entries, failures, mut stats) =
+                        split_parsed(parsed, false, args.max_channels)?;
+                    stats.duplicates = dedupe.then(|| {
+                        let superseded = dedupe_entries(&mut entries);
+                        report_duplicates(&superseded);
+                        superseded.len()
+                    });
+
+                    let current: Vec<SubscriptionLine> =
+                        entries.iter().map(|entry| entry.action.clone()).collect();
+                    for action in applied.iter().filter(|action| !current.contains(action)) {
+                        println!("- {}", action);
+                    }
+                    entries.retain(|entry| !applied.contains(&entry.action));
+                    for entry in &entries {
+                        println!("+ line {}: {}", entry.line_number, entry.action);
+                    }
+                    applied = current;
+                    if entries.is_empty() && failures.is_empty() {
+                        if !args.quiet {
+                            println!("Nothing to apply");
+                        }
+                        continue;
+                    }
+
+                    entries.sort_by_key(|entry| Reverse(entry.priority));
+                    if let Err(error) = report_file_results(
+                        apply(entries),
+                        failures,
+                        &stats,
+                        false,
+                        args.quiet,
+                        None,
+                    ) {
+                        eprintln!("Error: {}", error);
+                    }
+                }
+            }
+            ControlCommands::BulkClear {
+                file_path,
+                version,
+                time,
+                fail_fast,
+                retry,
+                retry_delay,
+                yes,
+            } => {
+                let version = version
+                    .as_deref()
+                    .or(args.default_version.as_deref())
+                    .ok_or(SubscriptionError::MissingVersion)?;
+                let (entries, failures, stats) = split_parsed(
+                    parse_clear_file(file_path, version)?,
+                    *fail_fast,
+                    args.max_channels,
+                )?;
+                confirm(
+                    *yes,
+                    &format!(
+                        "Clear {} receiver channel(s) listed in {}",
+                        entries.len(),
+                        file_path
+                    ),
+                )?;
+
+                let results = apply_entries(
+                    entries,
+                    1,
+                    *time,
+                    *fail_fast,
+                    &rate_limiter,
+                    RetryPolicy::new(*retry, *retry_delay),
+                    args.op_timeout,
+                    &completed_operations,
+                );
+                report_file_results(results, failures, &stats, *fail_fast, args.quiet, None)?;
+            }
+            ControlCommands::Clear {
+                version: first,
+                receiver_ip_string,
+                receiver_channel_index,
+                discovery_time,
+                probe_only,
+                yes,
+            } => {
+                let version = &command_version(
+                    first,
+                    receiver_channel_index.is_none(),
+                    "RECEIVER_CHANNEL_INDEX",
+                    args.default_version.as_deref(),
+                )?;
+                parse_version(version)?;
+                // Without the version, every positional landed one place early.
+                let (receiver_ip_string, receiver_channel_index) = match receiver_channel_index {
+                    Some(index) => (receiver_ip_string, *index),
+                    None => (first, channel_index_arg(receiver_ip_string)),
+                };
+                check_channel(receiver_channel_index, args.max_channels)?;
+
+                let mut discovery = SharedDiscovery::new(*discovery_time, discovery_cache(&args));
+                let receiver_ip = resolve_receiver(receiver_ip_string, &mut discovery, args.fuzzy)?;
+                if *probe_only {
+                    return probe(&[receiver_ip], &mut discovery, args.timing);
+                }
+                if args.timing {
+                    discovery.report_timing();
+                }
+                confirm(
+                    *yes,
+                    &format!(
+                        "Clear receiver channel {} on {}",
+                        receiver_channel_index, receiver_ip
+                    ),
+                )?;
+
+                let device_manager = discovery.device_manager();
+                let action = SubscriptionLine::Clear {
+                    version: version.clone(),
+                    receiver_ip: receiver_ip.to_string(),
+                    receiver_channel_index,
+                };
+                apply_action(
+                    device_manager,
+                    &action,
+                    &rate_limiter,
+                    RetryPolicy::new(0, Duration::ZERO),
+                    args.op_timeout,
+                )
+                .map_err(|error| error as Box<dyn std::error::Error>)?;
+                completed_operations.fetch_add(1, Ordering::SeqCst);
+            }
+            ControlCommands::ClearAll {
+                version: first,
+                receiver_ip_string,
+                range,
+                discovery_time,
+                yes,
+            } => {
+                let version = &command_version(
+                    first,
+                    receiver_ip_string.is_none(),
+                    "RECEIVER_IP_STRING",
+                    args.default_version.as_deref(),
+                )?;
+                parse_version(version)?;
+                // Without the version, the receiver landed in its place.
+                let receiver_ip_string = receiver_ip_string.as_ref().unwrap_or(first);
+                check_channel(*range.end(), args.max_channels)?;
+                let mut discovery = SharedDiscovery::new(*discovery_time, discovery_cache(&args));
+                let receiver_ip = resolve_receiver(receiver_ip_string, &mut discovery, args.fuzzy)?;
+                if args.timing {
+                    discovery.report_timing();
+                }
+                confirm(
+                    *yes,
+                    &format!(
+                        "Clear receiver channels {}-{} on {}",
+                        range.start(),
+                        range.end(),
+                        receiver_ip
+                    ),
+                )?;
+
+                let device_manager = discovery.device_manager();
+                let mut cleared = 0;
+                let mut first_error = None;
+                for receiver_channel_index in range.clone() {
+                    let action = SubscriptionLine::Clear {
+                        version: version.clone(),
+                        receiver_ip: receiver_ip.to_string(),
+                        receiver_channel_index,
+                    };
+                    let result = apply_action(
+                        device_manager,
+                        &action,
+                        &rate_limiter,
+                        RetryPolicy::new(0, Duration::ZERO),
+                        args.op_timeout,
+                    );
+                    match result {
+                        Ok(_) => {
+                            cleared += 1;
+                            completed_operations.fetch_add(1, Ordering::SeqCst);
+                        }
+                        Err(error) => {
+                            log::info!(
+                                "Channel {} wasn't cleared, it may already be empty: {}",
+                                receiver_channel_index,
+                                error
+                            );
+                            first_error.get_or_insert(error.to_string());
+                        }
+                    }
+                }
+
+                // Empty channels are expected to fail, but if none cleared the device is most
+                // likely rejecting every request.
+                if cleared == 0 {
+                    if let Some(error) = first_error {
+                        return Err(control_error(error).into());
+                    }
+                }
+                if !args.quiet {
+                    println!(
+                        "Cleared {} of {} channel(s) on {}",
+                        cleared,
+                        range.len(),
+                        receiver_ip
+                    );
+                }
+            }
+        },
+        Some(Commands::Resolve {
+            device_name,
+            // --stdin leaves device_name unset, which is all it takes.
+            stdin: _,
+            ip: reverse,
+            time,
+            format,
+        }) => {
+            if *reverse && *format != LookupFormat::Text {
+                Args::command()
+                    .error(
+                        ErrorKind::ArgumentConflict,
+                        "--format env only applies to looking up a name",
+                    )
+                    .exit()
+            }
+            let device_name = match device_name.as_deref() {
+                Some(name) if name != "-" => name,
+                _ if *reverse => {
+                    let lines = read_names(io::stdin().lock())?;
+                    let parsed: Vec<_> = lines.iter().map(|line| parse_ipv4(line)).collect();
+                    let ips: Vec<Ipv4Addr> = parsed
+                        .iter()
+                        .filter_map(|ip| ip.as_ref().ok())
+                        .copied()
+                        .collect();
+                    let found = if ips.is_empty() {
+                        Vec::new()
+                    } else {
+                        resolve_ips(&ips, *time, args.timing)?
+                    };
+
+                    // Every line gets an answer, and the exit code says whether any of them failed.
+                    let mut invalid = 0;
+                    let mut missing = 0;
+                    for (line, ip) in lines.iter().zip(&parsed) {
+                        let ip = match ip {
+                            Ok(ip) => ip,
+                            Err(error) => {
+                                eprintln!("{}", error);
+                                println!("{}\tNOT_FOUND", line);
+                                invalid += 1;
+                                continue;
+                            }
+                        };
+                        let names = found
+                            .iter()
+                            .find(|(found, _)| found == ip)
+                            .map(|(_, names)| names.as_slice())
+                            .unwrap_or_default();
+                        if names.is_empty() {
+                            println!("{}\tNOT_FOUND", ip);
+                            missing += 1;
+                        }
+                        for name in names {
+                            println!("{}\t{}", ip, name);
+                        }
+                    }
+                    if invalid > 0 {
+                        return Err(AddressError::InvalidLines(invalid).into());
+                    }
+                    if missing > 0 {
+                        return Err(ResolveError::NotFound(missing).into());
+                    }
+                    return Ok(());
+                }
+                _ => {
+                    if *format != LookupFormat::Text {
+                        Args::command()
+                            .error(
+                                ErrorKind::ArgumentConflict,
+                                "--format env only applies to a single name",
+                            )
+                            .exit()
+                    }
+                    let names = read_names(io::stdin().lock())?;
+                    let mut missing = 0;
+                    for (name, ip) in resolve_names(&names, *time, args.fuzzy, args.timing)? {
+                        match ip {
+                            Some(ip) => println!("{}\t{}", name, ip),
+                            None => {
+                                println!("{}\tNOT_FOUND", name);
+                                missing += 1;
+                            }
+                        }
+                    }
+                    if missing > 0 {
+                        return Err(ResolveError::NotFound(missing).into());
+                    }
+                    return Ok(());
+                }
+            };
+            if *reverse {
+                let ip = parse_ipv4(device_name)?;
+                let mut timing = DiscoveryTiming::start();
+                let names = discover_until(*time, &mut timing, |devices| names_for_ip(devices, ip));
+                if args.timing {
+                    timing.report();
+                }
+                let names = names.inspect_err(|error| {
+                    if error.is::<ResolveError>() {
+                        println!("NOT_FOUND");
+                    }
+                })?;
+                for name in names {
+                    println!("{}", name);
+                }
+                return Ok(());
+            }
+
+            let mut timing = DiscoveryTiming::start();
+            let ip = discover_until(*time, &mut timing, |devices| {
+                ip_for_name(devices, device_name, args.fuzzy)
+            });
+            if args.timing {
+                timing.report();
+            }
+            let ip = ip?;
+
+            print_lookup("DANTE_DEVICE_IP", &ip.to_string(), *format);
+        }
+        Some(Commands::Doctor {
+            time,
+            service_time,
+            format,
+        }) => {
+            let checks = run_checks(*time, &DEBUG_SERVICES, *service_time);
+            match format {
+                DoctorFormat::Text => print_checks(&checks),
+                DoctorFormat::Json => print_checks_json(&checks)?,
+            }
+
+            let failed = checks
+                .iter()
+                .filter(|check| check.status == CheckStatus::Fail)
+                .count();
+            if failed > 0 {
+                return Err(DoctorError::ChecksFailed(failed).into());
+            }
+        }
+        Some(Commands::Info) => {
+            println!("dante-cli {}", env!("CARGO_PKG_VERSION"));
+            println!("dante-control-rs {}", env!("DANTE_CONTROL_RS_VERSION"));
+            println!(
+                "Supported Dante versions: {}",
+                SUPPORTED_VERSIONS.join(", ")
+            );
+        }
+        Some(Commands::Completions { shell }) => {
+            generate(*shell, &mut Args::command(), "dante-cli", &mut io::stdout());
+        }
+        None => {
+            println!("No command specified. Try \"dante-cli help\"");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use subscription_file::parse_subscription_line;
+
+    #[test]
+    fn channel_range() {
+        assert_eq!(parse_channel_range("1-64"), Ok(1..=64));
+        assert_eq!(parse_channel_range(" 3 - 5 "), Ok(3..=5));
+    }
+
+    #[test]
+    fn single_channel_index() {
+        assert_eq!(parse_channel_range("7"), Ok(7..=7));
+    }
+
+    #[test]
+    fn reversed_channel_range() {
+        assert!(parse_channel_range("9-2").is_err());
+    }
+
+    #[test]
+    fn channel_index_zero() {
+        assert!(parse_channel_range("0").is_err());
+        assert!(parse_channel_range("0-4").is_err());
+    }
+
+    #[test]
+    fn non_numeric_channel_range() {
+        assert!(parse_channel_range("a-b").is_err());
+        assert!(parse_channel_range("1-").is_err());
+        assert!(parse_channel_range("all").is_err());
+    }
+
+    fn entries(lines: &[&str]) -> Vec<SubscriptionEntry> {
+        let mut entries: Vec<SubscriptionEntry> = lines
+            .iter()
+            .enumerate()
+            .map(|(index, line)| parse_subscription_line(line, index + 1, None).unwrap())
+            .collect();
+        entries.sort_by_key(|entry| Reverse(entry.priority));
+        entries
+    }
+
+    fn line_numbers(plan: Vec<Vec<Vec<SubscriptionEntry>>>) -> Vec<Vec<Vec<usize>>> {
+        plan.into_iter()
+            .map(|band| {
+                band.into_iter()
+                    .map(|group| group.iter().map(|entry| entry.line_number).collect())
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn control(args: &[&str]) -> ControlCommands {
+        let args = Args::try_parse_from(["dante-cli", "control"].iter().chain(args)).unwrap();
+        match args.command {
+            Some(Commands::Control(command)) => command,
+            command => panic!("expected a control command, got {:?}", command),
+        }
+    }
+
+    #[test]
+    fn version_positional() {
+        let ControlCommands::Make {
+            version,
+            receiver_channel_index,
+            ..
+        } = control(&["make", "4.4.1.3", "Tx", "01", "10.0.0.5", "3"])
+        else {
+            panic!("expected make");
+        };
+        assert_eq!(
+            command_version(&version, receiver_channel_index.is_empty(), "INDEX", None).unwrap(),
+            "4.4.1.3"
+        );
+    }
+
+    #[test]
+    fn version_left_out() {
+        let ControlCommands::Make {
+            version,
+            transmitter_name,
+            receiver_channel_index,
+            ..
+        } = control(&["make", "Tx", "01", "10.0.0.5", "3"])
+        else {
+            panic!("expected make");
+        };
+        assert_eq!((version.as_str(), transmitter_name.as_str()), ("Tx", "01"));
+        assert!(receiver_channel_index.is_empty());
+        assert_eq!(
+            command_version(&version, true, "INDEX", Some("4.2.1.3")).unwrap(),
+            "4.2.1.3"
+        );
+        assert!(matches!(
+            command_version(&version, true, "INDEX", None),
+            Err(SubscriptionError::MissingVersion)
+        ));
+
+        let ControlCommands::ClearAll {
+            version,
+            receiver_ip_string,
+            ..
+        } = control(&["clear-all", "10.0.0.5"])
+        else {
+            panic!("expected clear-all");
+        };
+        assert_eq!(version, "10.0.0.5");
+        assert_eq!(receiver_ip_string, None);
+    }
+
+    #[test]
+    fn higher_priority_bands_run_first() {
+        let entries = entries(&[
+            "4.4.1.3|A@Tx:1@10.0.0.5",
+            "4.4.1.3|B@Tx:1@10.0.0.6 @priority 10",
+            "4.4.1.3|C@Tx:2@10.0.0.5 @priority 10",
+            "4.4.1.3|D@Tx:2@10.0.0.6",
+            "4.4.1.3|E@Tx:3@10.0.0.5 @priority 10",
+        ]);
+        assert_eq!(
+            line_numbers(plan_work(entries, 4)),
+            vec![vec![vec![2], vec![3, 5]], vec![vec![1], vec![4]]]
+        );
+    }
+
+    #[test]
+    fn single_job_keeps_the_sorted_order() {
+        let entries = entries(&[
+            "4.4.1.3|A@Tx:1@10.0.0.5",
+            "4.4.1.3|B@Tx:1@10.0.0.6 @priority 10",
+            "4.4.1.3|C@Tx:2@10.0.0.5",
+        ]);
+        assert_eq!(
+            line_numbers(plan_work(entries, 1)),
+            vec![vec![vec![2, 1, 3]]]
+        );
+    }
 }
@@ -0,0 +1,699 @@
+use crate::devices::{ChangeKind, DeviceChange, DeviceInfo};
+use chrono::{DateTime, Utc};
+use serde::ser::SerializeMap;
+use serde::{Serialize, Serializer};
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::PathBuf;
+use std::process;
+
+const BOLD: &str = "\x1b[1m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+
+/// File name prefix for `monitor --output-dir`, so pruning never touches anything else in the
+/// directory.
+const INTERVAL_FILE_PREFIX: &str = "devices-";
+
+/// The columns `--table` draws unless `--fields` picks others.
+const TABLE_FIELDS: [DeviceField; 2] = [DeviceField::Name, DeviceField::Ip];
+/// The fields `--oneline` prints unless `--fields` picks others.
+const LINE_FIELDS: [DeviceField; 3] =
+    [DeviceField::Name, DeviceField::Ip, DeviceField::Description];
+/// Width of the `--table` address column, enough for any IPv4 address.
+const IP_WIDTH: usize = 15;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+    /// A header row, then one row per device. Only names are included unless --detailed is set
+    Csv,
+    /// The same structure as json, as a YAML document
+    Yaml,
+}
+
+/// When to color detailed device output.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Color when writing to a terminal and `NO_COLOR` isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to color output that goes to stdout, or to a file when `to_file` is set.
+    pub fn enabled(self, to_file: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                !to_file
+                    && io::stdout().is_terminal()
+                    && !env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+            }
+        }
+    }
+}
+
+/// When to render `list-devices --detailed` as a table.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableChoice {
+    /// Use a table when writing to a terminal, and the usual detailed output otherwise
+    Auto,
+    Always,
+}
+
+impl TableChoice {
+    /// Whether to draw the table for output that goes to stdout, or to a file when `to_file` is set.
+    pub fn enabled(self, to_file: bool) -> bool {
+        match self {
+            TableChoice::Always => true,
+            TableChoice::Auto => !to_file && io::stdout().is_terminal(),
+        }
+    }
+}
+
+/// Output format for `monitor`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MonitorFormat {
+    Text,
+    /// One JSON object per interval and line, `{"timestamp": "...", "devices": [...]}`, or
+    /// `{"timestamp": "...", "changes": [...]}` with --changes-only
+    Ndjson,
+}
+
+/// Output format for commands that look up a single value.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LookupFormat {
+    Text,
+    /// `VARIABLE=value`, escaped so it can be `eval`'d or sourced by a shell
+    Env,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum OutputError {
+    #[error("Could not open output file {path}: {source}")]
+    Open { path: String, source: io::Error },
+}
+
+/// A field of detailed device output, for `--fields`.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeviceField {
+    Name,
+    /// The IPv4 address, empty when the device has none
+    Ip,
+    /// The description the device manager reports, which may span several lines
+    Description,
+}
+
+impl DeviceField {
+    /// The name used for the field in csv headers and `--oneline` output.
+    fn name(self) -> &'static str {
+        match self {
+            DeviceField::Name => "name",
+            DeviceField::Ip => "ip",
+            DeviceField::Description => "description",
+        }
+    }
+
+    /// The key used in JSON and YAML, the same as in full detailed output.
+    fn key(self) -> &'static str {
+        match self {
+            DeviceField::Ip => "ipv4",
+            field => field.name(),
+        }
+    }
+
+    fn value(self, device: &DeviceInfo) -> String {
+        match self {
+            DeviceField::Name => device.name.clone(),
+            DeviceField::Ip => device.ipv4.map(|ip| ip.to_string()).unwrap_or_default(),
+            DeviceField::Description => device.description.clone(),
+        }
+    }
+}
+
+/// The JSON and YAML shape of a device with `--fields`: only those fields, in the order given.
+struct SelectedFields<'a> {
+    device: &'a DeviceInfo,
+    fields: &'a [DeviceField],
+}
+
+impl Serialize for SelectedFields<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.fields.len()))?;
+        for field in self.fields {
+            match field {
+                DeviceField::Name => map.serialize_entry(field.key(), &self.device.name)?,
+                DeviceField::Ip => map.serialize_entry(field.key(), &self.device.ipv4)?,
+                DeviceField::Description => {
+                    map.serialize_entry(field.key(), &self.device.description)?
+                }
+            }
+        }
+        map.end()
+    }
+}
+
+fn select_fields<'a>(
+    devices: &'a [DeviceInfo],
+    fields: &'a [DeviceField],
+) -> Vec<SelectedFields<'a>> {
+    devices
+        .iter()
+        .map(|device| SelectedFields { device, fields })
+        .collect()
+}
+
+/// The JSON shape of a device when `--detailed` isn't set.
+#[derive(Serialize)]
+struct DeviceName<'a> {
+    name: &'a str,
+}
+
+pub fn print_lookup(variable: &str, value: &str, format: LookupFormat) {
+    match format {
+        LookupFormat::Text => println!("{}", value),
+        LookupFormat::Env => println!("{}={}", variable, shellwords::escape(value)),
+    }
+}
+
+/// Where command output goes. A file that's replaced rather than appended to is written under a
+/// temporary name next to it and only renamed into place by `finish`, so a reader never sees it
+/// half-written, and a run that fails partway leaves the previous file as it was.
+pub struct Output {
+    writer: Box<dyn Write>,
+    /// The temporary file and the path it replaces, until `finish` renames it.
+    pending: Option<(PathBuf, PathBuf)>,
+}
+
+impl Output {
+    /// Flushes everything written so far and, the first time, renames a replaced file into place.
+    /// Anything written after that goes straight to the file.
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        if let Some((temp, path)) = self.pending.take() {
+            fs::rename(temp, path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        if let Some((temp, _)) = self.pending.take() {
+            let _ = fs::remove_file(temp);
+        }
+    }
+}
+
+/// Opens the file given by `--output`, replacing it unless `append` is set, or falls back to
+/// stdout when there is none. Call `Output::finish` once the output is complete.
+pub fn open_output(path: Option<&str>, append: bool) -> Result<Output, OutputError> {
+    let Some(path) = path else {
+        return Ok(Output {
+            writer: Box::new(io::stdout()),
+            pending: None,
+        });
+    };
+
+    let open_error = |source| OutputError::Open {
+        path: path.to_string(),
+        source,
+    };
+    if append {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(open_error)?;
+        return Ok(Output {
+            writer: Box::new(BufWriter::new(file)),
+            pending: None,
+        });
+    }
+
+    // The temporary file sits in the same directory so the rename can't cross filesystems.
+    let path = PathBuf::from(path);
+    let name = path.file_name().map_or_else(
+        || "output".into(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    let temp = path.with_file_name(format!(".{}.{}.tmp", name, process::id()));
+    let file = File::create(&temp).map_err(open_error)?;
+    Ok(Output {
+        writer: Box::new(BufWriter::new(file)),
+        pending: Some((temp, path)),
+    })
+}
+
+/// Writes each `monitor` interval into its own timestamped file under `--output-dir`.
+pub struct IntervalFiles {
+    dir: PathBuf,
+    extension: &'static str,
+    keep: Option<usize>,
+}
+
+impl IntervalFiles {
+    /// Creates `dir` if it doesn't exist yet. With `keep`, only that many of the newest interval
+    /// files are left behind each time one is written.
+    pub fn open(
+        dir: &str,
+        extension: &'static str,
+        keep: Option<usize>,
+    ) -> Result<Self, OutputError> {
+        fs::create_dir_all(dir).map_err(|source| OutputError::Open {
+            path: dir.to_string(),
+            source,
+        })?;
+        Ok(Self {
+            dir: PathBuf::from(dir),
+            extension,
+            keep,
+        })
+    }
+
+    /// Creates the file for the interval sampled at `timestamp`. Names use the basic ISO 8601 form,
+    /// without colons, so they're valid on every platform and sort in time order.
+    pub fn create(&self, timestamp: DateTime<Utc>) -> Result<Output, OutputError> {
+        let name = format!(
+            "{}{}.{}",
+            INTERVAL_FILE_PREFIX,
+            timestamp.format("%Y%m%dT%H%M%S%.3fZ"),
+            self.extension
+        );
+        let path = self.dir.join(name);
+        open_output(Some(&path.to_string_lossy()), false)
+    }
+
+    /// Deletes the oldest interval files beyond `--keep`.
+    pub fn prune(&self) -> io::Result<()> {
+        let Some(keep) = self.keep else {
+            return Ok(());
+        };
+
+        let suffix = format!(".{}", self.extension);
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(INTERVAL_FILE_PREFIX) && name.ends_with(&suffix) {
+                files.push(name);
+            }
+        }
+
+        files.sort();
+        let excess = files.len().saturating_sub(keep);
+        for name in &files[..excess] {
+            fs::remove_file(self.dir.join(name))?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints devices in `format`. Detailed output carries every field unless `fields` picks some.
+pub fn print_devices(
+    out: &mut dyn Write,
+    devices: &[DeviceInfo],
+    detailed: bool,
+    format: OutputFormat,
+    color: bool,
+    fields: Option<&[DeviceField]>,
+) -> io::Result<()> {
+    match (format, fields) {
+        (OutputFormat::Text, Some(fields)) if detailed => {
+            for device in devices {
+                for field in fields {
+                    match field {
+                        DeviceField::Description if color => {
+                            writeln!(out, "{}", highlight(device))?
+                        }
+                        DeviceField::Description => writeln!(out, "{}", device.description)?,
+                        DeviceField::Name if color => {
+                            writeln!(out, "name: {}{}{}", BOLD, device.name, RESET)?
+                        }
+                        DeviceField::Ip if color => {
+                            writeln!(out, "ip: {}{}{}", GREEN, field.value(device), RESET)?
+                        }
+                        field => writeln!(out, "{}: {}", field.name(), field.value(device))?,
+                    }
+                }
+                writeln!(out, "---------------------------------")?;
+            }
+        }
+        (OutputFormat::Json, Some(fields)) if detailed => writeln!(
+            out,
+            "{}",
+            serde_json::to_string_pretty(&select_fields(devices, fields))?
+        )?,
+        (OutputFormat::Csv, Some(fields)) if detailed => {
+            let header: Vec<&str> = fields.iter().map(|field| field.name()).collect();
+            writeln!(out, "{}", header.join(","))?;
+            for device in devices {
+                let row: Vec<String> = fields
+                    .iter()
+                    .map(|field| csv_field(&field.value(device)))
+                    .collect();
+                writeln!(out, "{}", row.join(","))?;
+            }
+        }
+        (OutputFormat::Yaml, Some(fields)) if detailed => {
+            write!(out, "{}", to_yaml(&select_fields(devices, fields))?)?
+        }
+        (OutputFormat::Text, _) => {
+            for device in devices {
+                if !detailed {
+                    writeln!(out, "{}", device.name)?;
+                } else if color {
+                    writeln!(out, "{}", highlight(device))?;
+                    writeln!(out, "---------------------------------")?;
+                } else {
+                    writeln!(out, "{}", device.description)?;
+                    writeln!(out, "---------------------------------")?;
+                }
+            }
+        }
+        (OutputFormat::Json, _) if detailed => {
+            writeln!(out, "{}", serde_json::to_string_pretty(devices)?)?
+        }
+        (OutputFormat::Json, _) => {
+            let names: Vec<DeviceName> = devices
+                .iter()
+                .map(|device| DeviceName { name: &device.name })
+                .collect();
+            writeln!(out, "{}", serde_json::to_string_pretty(&names)?)?
+        }
+        (OutputFormat::Csv, _) if detailed => {
+            writeln!(out, "name,ip,description")?;
+            for device in devices {
+                let ip = device.ipv4.map(|ip| ip.to_string()).unwrap_or_default();
+                writeln!(
+                    out,
+                    "{},{},{}",
+                    csv_field(&device.name),
+                    csv_field(&ip),
+                    csv_field(&device.description)
+                )?;
+            }
+        }
+        (OutputFormat::Csv, _) => {
+            writeln!(out, "name")?;
+            for device in devices {
+                writeln!(out, "{}", csv_field(&device.name))?;
+            }
+        }
+        (OutputFormat::Yaml, _) if detailed => write!(out, "{}", to_yaml(devices)?)?,
+        (OutputFormat::Yaml, _) => {
+            let names: Vec<DeviceName> = devices
+                .iter()
+                .map(|device| DeviceName { name: &device.name })
+                .collect();
+            write!(out, "{}", to_yaml(&names)?)?
+        }
+    }
+    Ok(())
+}
+
+/// Serializes to YAML, which always ends with a newline.
+fn to_yaml<T: Serialize + ?Sized>(value: &T) -> io::Result<String> {
+    serde_yaml::to_string(value).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+/// Prints devices as a table with a column for each of `fields`, or the name and the IPv4 address,
+/// sized to fit. Descriptions are folded onto one line.
+pub fn print_device_table(
+    out: &mut dyn Write,
+    devices: &[DeviceInfo],
+    fields: Option<&[DeviceField]>,
+) -> io::Result<()> {
+    let fields = fields.unwrap_or(&TABLE_FIELDS);
+    let rows: Vec<Vec<String>> = devices
+        .iter()
+        .map(|device| {
+            fields
+                .iter()
+                .map(|field| match field {
+                    DeviceField::Description => device
+                        .description
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    field => field.value(device),
+                })
+                .collect()
+        })
+        .collect();
+
+    let widths: Vec<usize> = fields
+        .iter()
+        .enumerate()
+        .map(|(column, field)| {
+            let minimum = match field {
+                DeviceField::Ip => IP_WIDTH,
+                field => field.name().len(),
+            };
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .chain([minimum])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let headers: Vec<String> = fields
+        .iter()
+        .map(|field| field.name().to_uppercase())
+        .collect();
+    write_table_row(out, &headers, &widths)?;
+    let rules: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    write_table_row(out, &rules, &widths)?;
+    for row in &rows {
+        write_table_row(out, row, &widths)?;
+    }
+    Ok(())
+}
+
+/// Writes one table row, padding every cell but the last to its column's width.
+fn write_table_row(out: &mut dyn Write, cells: &[String], widths: &[usize]) -> io::Result<()> {
+    let last = cells.len().saturating_sub(1);
+    let cells: Vec<String> = cells
+        .iter()
+        .zip(widths)
+        .enumerate()
+        .map(|(column, (cell, width))| {
+            if column == last {
+                cell.clone()
+            } else {
+                format!("{:<width$}", cell, width = width)
+            }
+        })
+        .collect();
+    writeln!(out, "{}", cells.join("  "))
+}
+
+/// Prints each device on one `name=.. ip=.. description=..` line, or with only `fields`, for
+/// grepping `--oneline` output.
+pub fn print_device_lines(
+    out: &mut dyn Write,
+    devices: &[DeviceInfo],
+    fields: Option<&[DeviceField]>,
+) -> io::Result<()> {
+    let fields = fields.unwrap_or(&LINE_FIELDS);
+    for device in devices {
+        let pairs: Vec<String> = fields
+            .iter()
+            .map(|field| {
+                format!(
+                    "{}={}",
+                    field.name(),
+                    line_value(field.value(device).trim())
+                )
+            })
+            .collect();
+        writeln!(out, "{}", pairs.join(" "))?;
+    }
+    Ok(())
+}
+
+/// Quotes a `--oneline` value when it's empty or has spaces, quotes, `=` or line breaks in it, so
+/// each device stays on a single line.
+fn line_value(value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Highlights the device name and address wherever they appear in its description.
+fn highlight(device: &DeviceInfo) -> String {
+    let mut description = device.description.clone();
+    if !device.name.is_empty() {
+        description =
+            description.replace(&device.name, &format!("{}{}{}", BOLD, device.name, RESET));
+    }
+    if let Some(ip) = device.ipv4 {
+        let ip = ip.to_string();
+        description = description.replace(&ip, &format!("{}{}{}", GREEN, ip, RESET));
+    }
+    description
+}
+
+/// Writes one `monitor --format ndjson` line. Devices carry only their names unless `detailed` is
+/// set, and only `fields` when given, as with `print_devices`.
+pub fn print_device_sample(
+    out: &mut dyn Write,
+    timestamp: &str,
+    devices: &[DeviceInfo],
+    detailed: bool,
+    fields: Option<&[DeviceField]>,
+) -> io::Result<()> {
+    let devices = if let (true, Some(fields)) = (detailed, fields) {
+        serde_json::to_value(select_fields(devices, fields))?
+    } else if detailed {
+        serde_json::to_value(devices)?
+    } else {
+        let names: Vec<DeviceName> = devices
+            .iter()
+            .map(|device| DeviceName { name: &device.name })
+            .collect();
+        serde_json::to_value(names)?
+    };
+
+    let sample = serde_json::json!({ "timestamp": timestamp, "devices": devices });
+    writeln!(out, "{}", sample)
+}
+
+/// Writes one `monitor --format ndjson --changes-only` line, with the changes in the same shape
+/// `print_device_changes` gives them in JSON.
+pub fn print_change_sample(
+    out: &mut dyn Write,
+    timestamp: &str,
+    changes: &[DeviceChange],
+) -> io::Result<()> {
+    let sample = serde_json::json!({ "timestamp": timestamp, "changes": changes });
+    writeln!(out, "{}", sample)
+}
+
+pub fn print_device_changes(
+    out: &mut dyn Write,
+    changes: &[DeviceChange],
+    format: OutputFormat,
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Text => {
+            for change in changes {
+                match change.change {
+                    ChangeKind::New => writeln!(out, "+ {}", change.name)?,
+                    ChangeKind::Removed => writeln!(out, "- {}", change.name)?,
+                    ChangeKind::Changed => {
+                        writeln!(out, "~ {}", change.name)?;
+                        for delta in &change.deltas {
+                            let before = delta.before.as_deref().unwrap_or("none");
+                            let after = delta.after.as_deref().unwrap_or("none");
+                            if before.contains('\n') || after.contains('\n') {
+                                writeln!(out, "    {} changed", delta.field)?;
+                            } else {
+                                writeln!(out, "    {}: {} -> {}", delta.field, before, after)?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        OutputFormat::Json => writeln!(out, "{}", serde_json::to_string_pretty(changes)?)?,
+        OutputFormat::Yaml => write!(out, "{}", to_yaml(changes)?)?,
+        OutputFormat::Csv => {
+            writeln!(out, "name,change,field,before,after")?;
+            for change in changes {
+                let kind = match change.change {
+                    ChangeKind::New => "new",
+                    ChangeKind::Changed => "changed",
+                    ChangeKind::Removed => "removed",
+                };
+                if change.deltas.is_empty() {
+                    writeln!(out, "{},{},,,", csv_field(&change.name), kind)?;
+                }
+                for delta in &change.deltas {
+                    writeln!(
+                        out,
+                        "{},{},{},{},{}",
+                        csv_field(&change.name),
+                        kind,
+                        delta.field,
+                        csv_field(delta.before.as_deref().unwrap_or_default()),
+                        csv_field(delta.after.as_deref().unwrap_or_default())
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Quotes a CSV field if it contains a delimiter, quote or line break.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printed(detailed: bool, format: OutputFormat) -> String {
+        let mut out = Vec::new();
+        print_devices(&mut out, &[], detailed, format, false, None).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn empty_text() {
+        assert_eq!(printed(false, OutputFormat::Text), "");
+        assert_eq!(printed(true, OutputFormat::Text), "");
+    }
+
+    #[test]
+    fn empty_json() {
+        assert_eq!(printed(false, OutputFormat::Json), "[]\n");
+        assert_eq!(printed(true, OutputFormat::Json), "[]\n");
+    }
+
+    #[test]
+    fn empty_csv() {
+        assert_eq!(printed(false, OutputFormat::Csv), "name\n");
+        assert_eq!(printed(true, OutputFormat::Csv), "name,ip,description\n");
+    }
+
+    #[test]
+    fn empty_yaml() {
+        assert_eq!(printed(false, OutputFormat::Yaml), "[]\n");
+        assert_eq!(printed(true, OutputFormat::Yaml), "[]\n");
+    }
+
+    #[test]
+    fn empty_table() {
+        let mut out = Vec::new();
+        print_device_table(&mut out, &[], None).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "NAME  IP\n----  ---------------\n"
+        );
+    }
+}
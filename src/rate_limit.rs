@@ -0,0 +1,94 @@
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// A token bucket holding a single token, shared by every control operation in an invocation.
+///
+/// Operations are spaced at least `1 / ops_per_second` apart no matter how many threads are
+/// issuing them, so a burst never goes out faster than the configured rate.
+pub struct RateLimiter {
+    interval: Option<Duration>,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// Creates a limiter for `ops_per_second`, or one that never waits when given `None`.
+    pub fn new(ops_per_second: Option<f32>) -> Self {
+        Self {
+            interval: ops_per_second.map(|rate| Duration::from_secs_f32(1.0 / rate)),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Blocks until the next operation is allowed to go out.
+    pub fn acquire(&self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+
+        let wait = {
+            let mut next_slot = self.next_slot.lock().expect("rate limiter lock poisoned");
+            let now = Instant::now();
+            let slot = (*next_slot).max(now);
+            *next_slot = slot + interval;
+            slot - now
+        };
+
+        if !wait.is_zero() {
+            sleep(wait);
+        }
+    }
+}
+
+/// Clap value parser for `--limit-rate`. Rates so small that the spacing between operations
+/// can't be held in a [`Duration`] are rejected here, so [`RateLimiter::new`] never sees them.
+pub fn parse_rate(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Duration::try_from_secs_f32(1.0 / rate)
+            .map(|_| rate)
+            .map_err(|_| format!("rate {} is too small", value)),
+        Ok(_) => Err("rate must be a positive number of operations per second".to_string()),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_positive_rates() {
+        assert_eq!(parse_rate("5"), Ok(5.0));
+        assert_eq!(parse_rate("0.5"), Ok(0.5));
+    }
+
+    #[test]
+    fn rejects_invalid_rates() {
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("-2").is_err());
+        assert!(parse_rate("inf").is_err());
+        assert!(parse_rate("NaN").is_err());
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("1e-39").is_err());
+    }
+
+    #[test]
+    fn spaces_operations() {
+        let limiter = RateLimiter::new(Some(20.0));
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn unlimited_never_waits() {
+        let limiter = RateLimiter::new(None);
+        let start = Instant::now();
+        for _ in 0..100 {
+            limiter.acquire();
+        }
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}
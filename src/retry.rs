@@ -0,0 +1,56 @@
+use std::fmt::Display;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// How many times to retry a failed operation, and how long to wait between attempts.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    retries: u32,
+    delay: Duration,
+    backoff: bool,
+}
+
+impl RetryPolicy {
+    pub fn new(retries: u32, delay: Duration) -> Self {
+        Self {
+            retries,
+            delay,
+            backoff: false,
+        }
+    }
+
+    /// Like `new`, but the wait doubles after every failed attempt.
+    pub fn with_backoff(retries: u32, initial_delay: Duration) -> Self {
+        Self {
+            backoff: true,
+            ..Self::new(retries, initial_delay)
+        }
+    }
+
+    /// Runs `operation` until it succeeds or the retries run out, returning the last error.
+    pub fn run<T, E: Display>(&self, mut operation: impl FnMut() -> Result<T, E>) -> Result<T, E> {
+        let mut attempt = 1;
+        loop {
+            match operation() {
+                Ok(value) => return Ok(value),
+                Err(error) if attempt <= self.retries => {
+                    let delay = if self.backoff {
+                        self.delay.saturating_mul(2u32.saturating_pow(attempt - 1))
+                    } else {
+                        self.delay
+                    };
+                    log::info!(
+                        "Attempt {} of {} failed: {}, retrying in {:?}",
+                        attempt,
+                        self.retries + 1,
+                        error,
+                        delay
+                    );
+                    sleep(delay);
+                    attempt += 1;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
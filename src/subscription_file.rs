@@ -0,0 +1,815 @@
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, Read};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ParsingError {
+    #[error("Could not properly detect @ between the transmitting channel and device name")]
+    TxDelimiter,
+    #[error("Could not properly detect @ between the receiving channel index and device name")]
+    RxDelimiter,
+    #[error("Could not properly detect | between the version and tx/rx devices, and no --default-version was given")]
+    VersionDelimiter,
+    #[error("Could not parse the receiving channel index into an integer")]
+    RxChanIndexParse,
+    #[error("Unbalanced double quotes")]
+    UnbalancedQuotes,
+    #[error("Quoted names must be wrapped entirely in double quotes")]
+    StrayQuote,
+    #[error("Could not parse the priority into an integer")]
+    PriorityParse,
+    #[error("Invalid subscription object: {0}")]
+    JsonEntry(String),
+    #[error("tx_device and tx_channel are required unless clear is true")]
+    MissingTransmitter,
+    #[error("No version was given, and no --default-version was set")]
+    MissingVersion,
+    #[error("Only ReceiverChannelIndex@ReceiverIp clears are allowed here")]
+    NotAClear,
+    #[error("Environment variable {0} is not set")]
+    UnsetVariable(String),
+    #[error("${{ without a closing }}")]
+    UnclosedVariable,
+}
+
+/// A `ParsingError` along with the line it came from.
+#[derive(thiserror::Error, Debug)]
+#[error("line {line}: {error}: {content}")]
+pub struct LineError {
+    /// 1-based line number.
+    pub line: usize,
+    pub content: String,
+    #[source]
+    pub error: ParsingError,
+}
+
+/// A subscription file that couldn't be read as a whole, as opposed to a bad line in it.
+#[derive(thiserror::Error, Debug)]
+pub enum FileError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("Not a JSON array of subscription objects: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// The `file_path` that reads the subscription file from stdin instead.
+const STDIN_PATH: &str = "-";
+
+/// Starts a comment line in a text subscription file.
+const COMMENT_PREFIX: char = '#';
+
+/// Opens an environment variable reference for `--expand-env`, closed by `}`.
+const VARIABLE_START: &str = "${";
+
+/// Marks the optional priority suffix on a line, as in `... @priority 10`.
+const PRIORITY_TOKEN: &str = "@priority";
+
+/// How a subscription file is written.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputFormat {
+    /// One `Version|Tx@Dev:Idx@Ip` line per action
+    Text,
+    /// An array of `{version, tx_device, tx_channel, rx_ip, rx_index}` objects, with `clear: true` for a clear
+    Json,
+}
+
+impl InputFormat {
+    /// Picks the format from the file extension, so `.json` files don't need `--input-format`.
+    pub fn for_path(path: &str) -> Self {
+        if path.to_ascii_lowercase().ends_with(".json") {
+            InputFormat::Json
+        } else {
+            InputFormat::Text
+        }
+    }
+}
+
+/// One object of a JSON subscription file.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct JsonSubscription {
+    version: Option<String>,
+    tx_device: Option<String>,
+    tx_channel: Option<String>,
+    rx_ip: String,
+    rx_index: u16,
+    #[serde(default)]
+    clear: bool,
+    #[serde(default)]
+    priority: i32,
+}
+
+/// A single action read from a subscription file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubscriptionLine {
+    /// `DanteVersion|TransmitterChannelName@TransmitterDeviceName:ReceiverChannelIndex@ReceiverIp`
+    Make {
+        version: String,
+        transmitter_name: String,
+        transmitter_channel_name: String,
+        receiver_ip: String,
+        receiver_channel_index: u16,
+    },
+    /// `DanteVersion|ReceiverChannelIndex@ReceiverIp`
+    Clear {
+        version: String,
+        receiver_ip: String,
+        receiver_channel_index: u16,
+    },
+}
+
+impl fmt::Display for SubscriptionLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubscriptionLine::Make {
+                version,
+                transmitter_name,
+                transmitter_channel_name,
+                receiver_ip,
+                receiver_channel_index,
+            } => write!(
+                f,
+                "subscribe {} channel {} to {}@{} (Dante {})",
+                receiver_ip,
+                receiver_channel_index,
+                transmitter_channel_name,
+                transmitter_name,
+                version
+            ),
+            SubscriptionLine::Clear {
+                version,
+                receiver_ip,
+                receiver_channel_index,
+            } => write!(
+                f,
+                "clear {} channel {} (Dante {})",
+                receiver_ip, receiver_channel_index, version
+            ),
+        }
+    }
+}
+
+impl SubscriptionLine {
+    /// The receiver channel this action changes, as `(receiver_ip, receiver_channel_index)`.
+    pub fn receiver(&self) -> (&str, u16) {
+        match self {
+            SubscriptionLine::Make {
+                receiver_ip,
+                receiver_channel_index,
+                ..
+            }
+            | SubscriptionLine::Clear {
+                receiver_ip,
+                receiver_channel_index,
+                ..
+            } => (receiver_ip, *receiver_channel_index),
+        }
+    }
+}
+
+/// A parsed line along with where it came from and how urgently it should be applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubscriptionEntry {
+    pub line_number: usize,
+    /// Entries with a higher priority are applied first. Lines without a priority get 0.
+    pub priority: i32,
+    pub action: SubscriptionLine,
+    /// The line as written in the file.
+    pub text: String,
+}
+
+/// Every line of a subscription file, parsed.
+#[derive(Debug)]
+pub struct ParsedFile {
+    pub entries: Vec<Result<SubscriptionEntry, LineError>>,
+    /// Blank and comment lines that were passed over.
+    pub skipped: usize,
+}
+
+/// Reads and parses a whole subscription file, in file order. Lines without a version use
+/// `default_version`.
+pub fn read_subscription_file(
+    path: &str,
+    default_version: Option<&str>,
+    format: InputFormat,
+    expand_env: bool,
+) -> Result<Vec<SubscriptionEntry>, Box<dyn std::error::Error>> {
+    let mut entries = Vec::new();
+    for entry in parse_subscription_file(path, default_version, format, expand_env)?.entries {
+        entries.push(entry?);
+    }
+    Ok(entries)
+}
+
+/// Parses every line of a subscription file, carrying on past lines that fail so they can all be
+/// reported at once. A `path` of `-` reads from stdin. Blank lines and `#` comments are skipped,
+/// and line numbers still count them.
+///
+/// JSON files are numbered by array entry, counting from 1, in place of line numbers.
+///
+/// With `expand_env`, `${VAR}` references are replaced by environment variables before a line is
+/// split on its delimiters, or in each string of a JSON object.
+pub fn parse_subscription_file(
+    path: &str,
+    default_version: Option<&str>,
+    format: InputFormat,
+    expand_env: bool,
+) -> Result<ParsedFile, FileError> {
+    let mut reader: Box<dyn BufRead> = if path == STDIN_PATH {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(io::BufReader::new(File::open(path)?))
+    };
+
+    if format == InputFormat::Json {
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents)?;
+        return Ok(ParsedFile {
+            entries: parse_json_subscriptions(&contents, default_version, expand_env)?,
+            skipped: 0,
+        });
+    }
+
+    let mut results = Vec::new();
+    let mut skipped = 0;
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(COMMENT_PREFIX) {
+            skipped += 1;
+            continue;
+        }
+
+        let line = if expand_env {
+            match expand_variables(line) {
+                Ok(line) => line,
+                Err(error) => {
+                    results.push(Err(LineError {
+                        line: line_index + 1,
+                        content: line.to_string(),
+                        error,
+                    }));
+                    continue;
+                }
+            }
+        } else {
+            Cow::Borrowed(line)
+        };
+        results.push(parse_subscription_line(
+            &line,
+            line_index + 1,
+            default_version,
+        ));
+    }
+    Ok(ParsedFile {
+        entries: results,
+        skipped,
+    })
+}
+
+/// Parses a `control bulk-clear` file, where every line is a clear and the version comes from
+/// `version` rather than a `DanteVersion|` prefix.
+pub fn parse_clear_file(path: &str, version: &str) -> Result<ParsedFile, FileError> {
+    let parsed = parse_subscription_file(path, Some(version), InputFormat::Text, false)?;
+    let entries = parsed
+        .entries
+        .into_iter()
+        .map(|result| match result {
+            Ok(entry) if matches!(entry.action, SubscriptionLine::Make { .. }) => Err(LineError {
+                line: entry.line_number,
+                content: entry.text,
+                error: ParsingError::NotAClear,
+            }),
+            result => result,
+        })
+        .collect();
+    Ok(ParsedFile {
+        entries,
+        skipped: parsed.skipped,
+    })
+}
+
+/// Parses one line of the subscription file grammar.
+///
+/// Any name may be wrapped in double quotes so it can contain the `|`, `:` and `@` delimiters,
+/// e.g. `4.4.1.3|"Tx@Special"@Device:3@10.0.0.5`. Inside quotes, `\"` is a literal quote and `\\` a
+/// literal backslash. Unquoted lines parse exactly as before.
+///
+/// A line may end with ` @priority N` (separated by whitespace) to be applied ahead of lines with a
+/// lower priority.
+///
+/// The `DanteVersion|` prefix may be left out when a `default_version` is given.
+pub fn parse_subscription_line(
+    line: &str,
+    line_number: usize,
+    default_version: Option<&str>,
+) -> Result<SubscriptionEntry, LineError> {
+    let parsed = split_priority(line)
+        .and_then(|(body, priority)| Ok((priority, parse_action(body, default_version)?)));
+
+    match parsed {
+        Ok((priority, action)) => Ok(SubscriptionEntry {
+            line_number,
+            priority,
+            action,
+            text: line.to_string(),
+        }),
+        Err(error) => Err(LineError {
+            line: line_number,
+            content: line.to_string(),
+            error,
+        }),
+    }
+}
+
+/// Parses a JSON array of subscription objects. A file that isn't an array at all is an error;
+/// objects that don't fit are reported one by one like bad lines.
+fn parse_json_subscriptions(
+    contents: &str,
+    default_version: Option<&str>,
+    expand_env: bool,
+) -> Result<Vec<Result<SubscriptionEntry, LineError>>, serde_json::Error> {
+    let values: Vec<serde_json::Value> = serde_json::from_str(contents)?;
+
+    Ok(values
+        .into_iter()
+        .enumerate()
+        .map(|(index, mut value)| {
+            let text = value.to_string();
+            let expanded = if expand_env {
+                expand_json_strings(&mut value)
+            } else {
+                Ok(())
+            };
+            let parsed = expanded
+                .and_then(|()| {
+                    serde_json::from_value(value)
+                        .map_err(|error| ParsingError::JsonEntry(error.to_string()))
+                })
+                .and_then(|subscription| json_entry(subscription, default_version));
+
+            match parsed {
+                Ok((priority, action)) => Ok(SubscriptionEntry {
+                    line_number: index + 1,
+                    priority,
+                    action,
+                    text,
+                }),
+                Err(error) => Err(LineError {
+                    line: index + 1,
+                    content: text,
+                    error,
+                }),
+            }
+        })
+        .collect())
+}
+
+/// Replaces each `${VAR}` in `text` with that environment variable, failing on the first one that
+/// isn't set.
+fn expand_variables(text: &str) -> Result<Cow<'_, str>, ParsingError> {
+    if !text.contains(VARIABLE_START) {
+        return Ok(Cow::Borrowed(text));
+    }
+
+    let mut expanded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(VARIABLE_START) {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + VARIABLE_START.len()..];
+        let end = after.find('}').ok_or(ParsingError::UnclosedVariable)?;
+        let name = &after[..end];
+        let value = env::var(name).map_err(|_| ParsingError::UnsetVariable(name.to_string()))?;
+        expanded.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(Cow::Owned(expanded))
+}
+
+/// Expands `${VAR}` references in the string fields of a JSON subscription object.
+fn expand_json_strings(value: &mut serde_json::Value) -> Result<(), ParsingError> {
+    if let serde_json::Value::Object(fields) = value {
+        for field in fields.values_mut() {
+            if let serde_json::Value::String(text) = field {
+                if let Cow::Owned(expanded) = expand_variables(text)? {
+                    *text = expanded;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn json_entry(
+    subscription: JsonSubscription,
+    default_version: Option<&str>,
+) -> Result<(i32, SubscriptionLine), ParsingError> {
+    let version = subscription
+        .version
+        .or_else(|| default_version.map(str::to_string))
+        .ok_or(ParsingError::MissingVersion)?;
+
+    let action = if subscription.clear {
+        SubscriptionLine::Clear {
+            version,
+            receiver_ip: subscription.rx_ip,
+            receiver_channel_index: subscription.rx_index,
+        }
+    } else {
+        let (Some(transmitter_name), Some(transmitter_channel_name)) =
+            (subscription.tx_device, subscription.tx_channel)
+        else {
+            return Err(ParsingError::MissingTransmitter);
+        };
+        SubscriptionLine::Make {
+            version,
+            transmitter_name,
+            transmitter_channel_name,
+            receiver_ip: subscription.rx_ip,
+            receiver_channel_index: subscription.rx_index,
+        }
+    };
+    Ok((subscription.priority, action))
+}
+
+/// Splits an optional trailing `@priority N` off a line. Whitespace inside a quoted name doesn't
+/// count, so a name can end in something that looks like a priority.
+fn split_priority(line: &str) -> Result<(&str, i32), ParsingError> {
+    let trimmed = line.trim_end();
+    let (unquoted, _) = unquoted_chars(trimmed);
+    let Some(&(index, space)) = unquoted.iter().rev().find(|(_, c)| c.is_whitespace()) else {
+        return Ok((line, 0));
+    };
+    let (rest, value) = (&trimmed[..index], &trimmed[index + space.len_utf8()..]);
+    match rest.trim_end().strip_suffix(PRIORITY_TOKEN) {
+        Some(body) if body.ends_with(char::is_whitespace) => {
+            let priority = value.parse().map_err(|_| ParsingError::PriorityParse)?;
+            Ok((body.trim_end(), priority))
+        }
+        _ => Ok((line, 0)),
+    }
+}
+
+/// Parses the action a line describes, without any priority suffix.
+fn parse_action(
+    line: &str,
+    default_version: Option<&str>,
+) -> Result<SubscriptionLine, ParsingError> {
+    if unquoted_chars(line).1 {
+        return Err(ParsingError::UnbalancedQuotes);
+    }
+
+    let (version, command) = match split_once_unquoted(line, '|') {
+        Some((version, command)) => (unquote(version)?, command),
+        None => {
+            let version = default_version.ok_or(ParsingError::VersionDelimiter)?;
+            (version.to_string(), line)
+        }
+    };
+
+    if let Some((tx, rx)) = split_once_unquoted(command, ':') {
+        let (tx_chan, tx_device) = split_once_unquoted(tx, '@').ok_or(ParsingError::TxDelimiter)?;
+        let (receiver_channel_index, receiver_ip) = parse_receiver(rx)?;
+
+        Ok(SubscriptionLine::Make {
+            version,
+            transmitter_name: unquote(tx_device)?,
+            transmitter_channel_name: unquote(tx_chan)?,
+            receiver_ip,
+            receiver_channel_index,
+        })
+    } else {
+        let (receiver_channel_index, receiver_ip) = parse_receiver(command)?;
+
+        Ok(SubscriptionLine::Clear {
+            version,
+            receiver_ip,
+            receiver_channel_index,
+        })
+    }
+}
+
+/// Parses the `ReceiverChannelIndex@ReceiverIp` half of a line.
+fn parse_receiver(rx: &str) -> Result<(u16, String), ParsingError> {
+    let (rx_chan, rx_ip) = split_once_unquoted(rx, '@').ok_or(ParsingError::RxDelimiter)?;
+    let rx_chan_index = unquote(rx_chan)?
+        .parse()
+        .map_err(|_| ParsingError::RxChanIndexParse)?;
+
+    Ok((rx_chan_index, unquote(rx_ip)?))
+}
+
+/// Returns every character of `input` outside double-quoted sections, with its byte offset, and
+/// whether a quote was left open at the end. A backslash inside quotes escapes the character after
+/// it.
+fn unquoted_chars(input: &str) -> (Vec<(usize, char)>, bool) {
+    let mut unquoted = Vec::new();
+    let mut quoted = false;
+    let mut chars = input.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' if quoted => {
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if !quoted => unquoted.push((index, c)),
+            _ => {}
+        }
+    }
+    (unquoted, quoted)
+}
+
+/// Splits `input` at the first `delimiter` that isn't inside a double-quoted section.
+fn split_once_unquoted(input: &str, delimiter: char) -> Option<(&str, &str)> {
+    let (unquoted, _) = unquoted_chars(input);
+    unquoted
+        .into_iter()
+        .find(|(_, c)| *c == delimiter)
+        .map(|(index, c)| (&input[..index], &input[index + c.len_utf8()..]))
+}
+
+/// Strips the surrounding double quotes from a name, if it has them, and resolves its `\"` and `\\`
+/// escapes. Any other backslash is kept as it is.
+fn unquote(token: &str) -> Result<String, ParsingError> {
+    let Some(inner) = token.strip_prefix('"') else {
+        if token.contains('"') {
+            return Err(ParsingError::StrayQuote);
+        }
+        return Ok(token.to_string());
+    };
+
+    let mut name = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped @ ('"' | '\\')) => name.push(escaped),
+                Some(other) => {
+                    name.push('\\');
+                    name.push(other);
+                }
+                None => return Err(ParsingError::UnbalancedQuotes),
+            },
+            '"' if chars.as_str().is_empty() => return Ok(name),
+            '"' => return Err(ParsingError::StrayQuote),
+            c => name.push(c),
+        }
+    }
+    Err(ParsingError::UnbalancedQuotes)
+}
+
+/// Drops every entry that a later entry for the same receiver channel supersedes, keeping the
+/// rest in order. Returns the dropped line numbers, each paired with the line that replaced it.
+pub fn dedupe_entries(entries: &mut Vec<SubscriptionEntry>) -> Vec<(usize, usize)> {
+    let mut superseded = Vec::new();
+    let mut kept: Vec<SubscriptionEntry> = Vec::with_capacity(entries.len());
+    for entry in entries.drain(..).rev() {
+        match kept
+            .iter()
+            .find(|later| later.action.receiver() == entry.action.receiver())
+        {
+            Some(later) => superseded.push((entry.line_number, later.line_number)),
+            None => kept.push(entry),
+        }
+    }
+    kept.reverse();
+    superseded.reverse();
+    *entries = kept;
+    superseded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<SubscriptionEntry, LineError> {
+        parse_subscription_line(line, 7, None)
+    }
+
+    fn make(line: &str) -> (String, String, String, u16) {
+        match parse(line).expect("line should parse").action {
+            SubscriptionLine::Make {
+                transmitter_name,
+                transmitter_channel_name,
+                receiver_ip,
+                receiver_channel_index,
+                ..
+            } => (
+                transmitter_channel_name,
+                transmitter_name,
+                receiver_ip,
+                receiver_channel_index,
+            ),
+            action => panic!("expected a subscription, got {:?}", action),
+        }
+    }
+
+    #[test]
+    fn unquoted_line() {
+        assert_eq!(
+            make("4.4.1.3|Tx@Device:3@10.0.0.5"),
+            ("Tx".into(), "Device".into(), "10.0.0.5".into(), 3)
+        );
+    }
+
+    #[test]
+    fn quoted_name_with_at() {
+        assert_eq!(
+            make(r#"4.4.1.3|"Tx@Special"@Device:3@10.0.0.5"#),
+            ("Tx@Special".into(), "Device".into(), "10.0.0.5".into(), 3)
+        );
+    }
+
+    #[test]
+    fn quoted_name_with_colon() {
+        assert_eq!(
+            make(r#"4.4.1.3|Tx@"Stage:Left":3@10.0.0.5"#),
+            ("Tx".into(), "Stage:Left".into(), "10.0.0.5".into(), 3)
+        );
+    }
+
+    #[test]
+    fn quoted_name_with_pipe() {
+        assert_eq!(
+            make(r#"4.4.1.3|"In|Out"@Device:3@10.0.0.5"#),
+            ("In|Out".into(), "Device".into(), "10.0.0.5".into(), 3)
+        );
+    }
+
+    #[test]
+    fn escaped_quotes() {
+        assert_eq!(
+            make(r#"4.4.1.3|"Say \"hi\""@"Back\\slash":3@10.0.0.5"#),
+            (
+                r#"Say "hi""#.into(),
+                r"Back\slash".into(),
+                "10.0.0.5".into(),
+                3
+            )
+        );
+    }
+
+    #[test]
+    fn unterminated_quote() {
+        let error = parse(r#"4.4.1.3|"Tx@Device:3@10.0.0.5"#).unwrap_err();
+        assert_eq!(error.line, 7);
+        assert!(matches!(error.error, ParsingError::UnbalancedQuotes));
+    }
+
+    #[test]
+    fn stray_quote() {
+        let error = parse(r#"4.4.1.3|Tx"x"@Device:3@10.0.0.5"#).unwrap_err();
+        assert!(matches!(error.error, ParsingError::StrayQuote));
+    }
+
+    #[test]
+    fn priority() {
+        let entry = parse("4.4.1.3|Tx@Device:3@10.0.0.5 @priority 5").unwrap();
+        assert_eq!(entry.priority, 5);
+    }
+
+    #[test]
+    fn priority_inside_quotes() {
+        let entry = parse(r#"4.4.1.3|"Tx @priority 5"@Device:3@10.0.0.5"#).unwrap();
+        assert_eq!(entry.priority, 0);
+        assert_eq!(
+            make(r#"4.4.1.3|"Tx @priority 5"@Device:3@10.0.0.5"#).0,
+            "Tx @priority 5"
+        );
+    }
+
+    #[test]
+    fn clear_line() {
+        let entry = parse("4.4.1.3|3@10.0.0.5").unwrap();
+        assert_eq!(
+            entry.action,
+            SubscriptionLine::Clear {
+                version: "4.4.1.3".into(),
+                receiver_ip: "10.0.0.5".into(),
+                receiver_channel_index: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn expands_variables() {
+        env::set_var("DANTE_CLI_TEST_DEVICE", "Stage");
+        env::set_var("DANTE_CLI_TEST_IP", "10.0.0.5");
+        assert_eq!(
+            expand_variables("4.4.1.3|Tx@${DANTE_CLI_TEST_DEVICE}:3@${DANTE_CLI_TEST_IP}").unwrap(),
+            "4.4.1.3|Tx@Stage:3@10.0.0.5"
+        );
+    }
+
+    #[test]
+    fn text_without_variables() {
+        assert!(matches!(
+            expand_variables("4.4.1.3|Tx@Device:3@10.0.0.5"),
+            Ok(Cow::Borrowed("4.4.1.3|Tx@Device:3@10.0.0.5"))
+        ));
+    }
+
+    #[test]
+    fn unset_variable() {
+        env::remove_var("DANTE_CLI_TEST_UNSET");
+        assert!(matches!(
+            expand_variables("4.4.1.3|Tx@${DANTE_CLI_TEST_UNSET}:3@10.0.0.5"),
+            Err(ParsingError::UnsetVariable(name)) if name == "DANTE_CLI_TEST_UNSET"
+        ));
+    }
+
+    #[test]
+    fn unclosed_variable() {
+        assert!(matches!(
+            expand_variables("4.4.1.3|Tx@${DEVICE:3@10.0.0.5"),
+            Err(ParsingError::UnclosedVariable)
+        ));
+    }
+
+    #[test]
+    fn later_lines_supersede_earlier_ones() {
+        let mut entries: Vec<SubscriptionEntry> = [
+            (1, "4.4.1.3|A@Device:3@10.0.0.5"),
+            (2, "4.4.1.3|B@Device:4@10.0.0.5"),
+            (3, "4.4.1.3|C@Device:3@10.0.0.5"),
+            (4, "4.4.1.3|3@10.0.0.5"),
+            (5, "4.4.1.3|D@Device:3@10.0.0.6"),
+        ]
+        .into_iter()
+        .map(|(line_number, line)| parse_subscription_line(line, line_number, None).unwrap())
+        .collect();
+
+        let superseded = dedupe_entries(&mut entries);
+        assert_eq!(superseded, vec![(1, 4), (3, 4)]);
+        let kept: Vec<usize> = entries.iter().map(|entry| entry.line_number).collect();
+        assert_eq!(kept, vec![2, 4, 5]);
+        assert!(matches!(entries[1].action, SubscriptionLine::Clear { .. }));
+    }
+
+    fn json(contents: &str) -> Vec<Result<SubscriptionEntry, LineError>> {
+        parse_json_subscriptions(contents, None, false).expect("file should be an array")
+    }
+
+    #[test]
+    fn json_array() {
+        let entries = json(
+            r#"[{"version": "4.4.1.3", "tx_device": "Device", "tx_channel": "Tx",
+                 "rx_ip": "10.0.0.5", "rx_index": 3, "priority": 2}]"#,
+        );
+        let entry = entries[0].as_ref().unwrap();
+        assert_eq!(entry.line_number, 1);
+        assert_eq!(entry.priority, 2);
+        assert_eq!(
+            entry.action,
+            SubscriptionLine::Make {
+                version: "4.4.1.3".into(),
+                transmitter_name: "Device".into(),
+                transmitter_channel_name: "Tx".into(),
+                receiver_ip: "10.0.0.5".into(),
+                receiver_channel_index: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn json_bad_object() {
+        let entries = json(
+            r#"[{"version": "4.4.1.3", "rx_ip": "10.0.0.5", "rx_index": 1, "clear": true},
+                {"version": "4.4.1.3", "rx_ip": "10.0.0.5"},
+                {"version": "4.4.1.3", "rx_ip": "10.0.0.5", "rx_index": 2}]"#,
+        );
+        assert_eq!(entries.len(), 3);
+        assert!(entries[0].is_ok());
+        let error = entries[1].as_ref().unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(matches!(error.error, ParsingError::JsonEntry(_)));
+        let error = entries[2].as_ref().unwrap_err();
+        assert_eq!(error.line, 3);
+        assert!(matches!(error.error, ParsingError::MissingTransmitter));
+    }
+
+    #[test]
+    fn json_clear() {
+        let entries =
+            json(r#"[{"version": "4.4.1.3", "rx_ip": "10.0.0.5", "rx_index": 3, "clear": true}]"#);
+        assert_eq!(
+            entries[0].as_ref().unwrap().action,
+            SubscriptionLine::Clear {
+                version: "4.4.1.3".into(),
+                receiver_ip: "10.0.0.5".into(),
+                receiver_channel_index: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn json_not_an_array() {
+        assert!(parse_json_subscriptions(r#"{"rx_ip": "10.0.0.5"}"#, None, false).is_err());
+        assert!(parse_json_subscriptions("[{", None, false).is_err());
+    }
+}